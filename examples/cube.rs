@@ -30,6 +30,8 @@ pub struct Vertex {
 fn main() -> anyhow::Result<()> {
     let mut window = Window::new("cube", WIDTH as _, HEIGHT as _, Default::default()).unwrap();
     let mut buffer = ImageBuffer::with_size([WIDTH, HEIGHT].into());
+    let mut target = soft::MultisampleBuffer::new(buffer.size, soft::SampleCount::Four);
+    let rasterizer = soft::Rasterizer::parallel();
 
     let mut shaders = Shaders {
         time: 0.0,
@@ -136,8 +138,9 @@ fn main() -> anyhow::Result<()> {
             projection * view * model
         };
 
-        buffer.pixels.iter_mut().for_each(|pixel| *pixel = 0x303030);
-        soft::draw(&mut buffer, &shaders, &vertices, &triangles);
+        target.clear(color_from_pixel(0x303030), 1.0);
+        rasterizer.draw_multisampled(&mut target, &shaders, &vertices, &triangles);
+        target.resolve(&mut buffer);
         window
             .update_with_buffer(
                 &buffer.pixels,
@@ -167,6 +170,11 @@ impl soft::PixelBuffer for ImageBuffer {
         self.size
     }
 
+    fn get(&self, pixel: soft::PixelCoord) -> soft::Color {
+        let index = pixel.x + pixel.y * self.size.width;
+        color_from_pixel(self.pixels[index as usize])
+    }
+
     fn set(&mut self, pixel: soft::PixelCoord, color: soft::Color) {
         debug_assert!(pixel.x < self.size.width, "pixel out of bounds");
         debug_assert!(pixel.y < self.size.height, "pixel out of bounds");
@@ -176,6 +184,11 @@ impl soft::PixelBuffer for ImageBuffer {
     }
 }
 
+fn color_from_pixel(pixel: Pixel) -> soft::Color {
+    let channel = |shift: u32| ((pixel >> shift) & 0xff) as f32 / 255.0;
+    [channel(16), channel(8), channel(0)].into()
+}
+
 fn pixel_from_color(color: soft::Color) -> Pixel {
     let r = (255.0 * color.r) as u8 as u32;
     let g = (255.0 * color.g) as u8 as u32;
@@ -183,7 +196,7 @@ fn pixel_from_color(color: soft::Color) -> Pixel {
     (r << 16) | (g << 8) | b
 }
 
-#[derive(soft::Interpolate)]
+#[derive(Clone, soft::Interpolate)]
 struct FragData {
     color: soft::Color,
     tex_coord: Vector2,
@@ -192,6 +205,7 @@ struct FragData {
 impl soft::ShaderModule for Shaders {
     type VertexInput = Vertex;
     type FragmentInput = FragData;
+    type Output = soft::Color;
 
     const FRONT_FACE: Option<soft::WindingOrder> = Some(soft::WindingOrder::CounterClockwise);
 
@@ -206,7 +220,8 @@ impl soft::ShaderModule for Shaders {
         )
     }
 
-    fn fragment_shader(&self, input: &Self::FragmentInput) -> soft::Color {
+    fn fragment_shader(&self, fragment: &soft::Fragment<Self::FragmentInput>) -> soft::Color {
+        let input = fragment.input();
         self.texture.sample_nearest_repeat(2.5 * input.tex_coord) * input.color
     }
 }
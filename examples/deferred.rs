@@ -0,0 +1,139 @@
+//! Render a pyramid into a G-buffer holding the albedo and normal of every pixel, then light the
+//! pyramid in a separate pass and save the result to an image file.
+//!
+//! Usage: `cargo run --example deferred [output.png|output.tga|output.ppm]`
+
+use soft::{Color, Vector3};
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 512;
+
+struct Shaders;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Vertex {
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub albedo: Color,
+}
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "deferred.png".to_owned());
+
+    // every side of the pyramid has its own vertices, since they have different normals
+    let apex = Vector3::new(0.0, 0.0, 0.2);
+    let corner = |x, y| Vector3::new(x, y, 0.5);
+    let sides = [
+        (
+            [corner(0.6, -0.6), corner(0.6, 0.6)],
+            [0.5, 0.0],
+            [1.0, 0.3, 0.2],
+        ),
+        (
+            [corner(0.6, 0.6), corner(-0.6, 0.6)],
+            [0.0, 0.5],
+            [0.3, 1.0, 0.2],
+        ),
+        (
+            [corner(-0.6, 0.6), corner(-0.6, -0.6)],
+            [-0.5, 0.0],
+            [0.2, 0.3, 1.0],
+        ),
+        (
+            [corner(-0.6, -0.6), corner(0.6, -0.6)],
+            [0.0, -0.5],
+            [1.0, 1.0, 0.2],
+        ),
+    ];
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    for ([a, b], [x, y], albedo) in sides.iter().copied() {
+        let normal = Vector3::new(x, y, -1.0).normalized();
+        let first = vertices.len() as u32;
+        vertices.extend([apex, a, b].iter().map(|&position| Vertex {
+            position,
+            normal,
+            albedo: albedo.into(),
+        }));
+        triangles.push([first, first + 1, first + 2].into());
+    }
+
+    let size = soft::Dimensions::from([WIDTH, HEIGHT]);
+    let mut albedo = soft::Image::new(size);
+    let mut normals = soft::Image::new(size);
+    normals.clear(Color::TRANSPARENT);
+    let mut depth = soft::DepthBuffer::new(size);
+    soft::Rasterizer::parallel().draw(
+        &mut (&mut albedo, &mut normals),
+        Some(&mut depth),
+        &Shaders,
+        &vertices,
+        &triangles,
+    );
+
+    // lighting pass, pixels without a normal are not covered by the pyramid
+    let light = Vector3::new(-0.5, 0.6, -1.0).normalized();
+    let background = Color::from([0.1, 0.1, 0.15]);
+    let pixels = albedo
+        .pixels()
+        .iter()
+        .zip(normals.pixels())
+        .map(|(albedo, normal)| {
+            if normal.a == 0.0 {
+                return background;
+            }
+            let normal =
+                Vector3::new(normal.r, normal.g, normal.b) * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+            let diffuse = normal.dot(light).max(0.0);
+            Color {
+                a: 1.0,
+                ..*albedo * (0.2 + 0.8 * diffuse)
+            }
+        })
+        .collect();
+
+    let image = soft::Image::from_pixels(size, pixels);
+    image.save(&path)?;
+
+    println!("saved {}", path);
+    Ok(())
+}
+
+#[derive(Clone, soft::Interpolate)]
+struct FragData {
+    normal: Vector3,
+    albedo: Color,
+}
+
+/// The G-buffer, with one color for every attachment of the render target.
+#[derive(soft::FragmentOutput)]
+struct GBuffer {
+    albedo: Color,
+    normal: Color,
+}
+
+impl soft::ShaderModule for Shaders {
+    type VertexInput = Vertex;
+    type FragmentInput = FragData;
+    type Output = GBuffer;
+
+    fn vertex_shader(&self, input: &Self::VertexInput) -> (soft::Vector4, Self::FragmentInput) {
+        let data = FragData {
+            normal: input.normal,
+            albedo: input.albedo,
+        };
+        (input.position.extend(1.0), data)
+    }
+
+    fn fragment_shader(&self, fragment: &soft::Fragment<Self::FragmentInput>) -> GBuffer {
+        let input = fragment.input();
+        // pack the normal into the range of a color
+        let normal = input.normal.normalized() * 0.5 + Vector3::new(0.5, 0.5, 0.5);
+        GBuffer {
+            albedo: input.albedo,
+            normal: normal.into(),
+        }
+    }
+}
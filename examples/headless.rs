@@ -0,0 +1,61 @@
+//! Render a triangle without opening a window, and save it to an image file.
+//!
+//! Usage: `cargo run --example headless [output.png|output.tga|output.ppm]`
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 512;
+
+struct Shaders;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Vertex {
+    pub position: soft::Vector3,
+    pub color: soft::Color,
+}
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "triangle.png".to_owned());
+
+    let vertex = |[x, y]: [f32; 2], [r, g, b]: [f32; 3]| Vertex {
+        position: soft::Vector3 { x, y, z: 0.0 },
+        color: soft::Color { r, g, b, a: 1.0 },
+    };
+    let vertices = vec![
+        vertex([0.0, 0.5], [1.0, 0.0, 0.0]),
+        vertex([0.5, -0.5], [1.0, 1.0, 0.0]),
+        vertex([-0.5, -0.5], [1.0, 0.0, 1.0]),
+    ];
+    let triangles = vec![[0, 1, 2].into()];
+
+    let size = soft::Dimensions::from([WIDTH, HEIGHT]);
+    let mut target = soft::MultisampleBuffer::new(size, soft::SampleCount::Four);
+    soft::Rasterizer::parallel().draw_multisampled(&mut target, &Shaders, &vertices, &triangles);
+
+    let mut image = soft::Image::new(size);
+    target.resolve(&mut image);
+    image.save(&path)?;
+
+    println!("saved {}", path);
+    Ok(())
+}
+
+#[derive(Clone, soft::Interpolate)]
+struct FragData {
+    color: soft::Color,
+}
+
+impl soft::ShaderModule for Shaders {
+    type VertexInput = Vertex;
+    type FragmentInput = FragData;
+    type Output = soft::Color;
+
+    fn vertex_shader(&self, input: &Self::VertexInput) -> (soft::Vector4, Self::FragmentInput) {
+        (input.position.extend(1.0), FragData { color: input.color })
+    }
+
+    fn fragment_shader(&self, fragment: &soft::Fragment<Self::FragmentInput>) -> soft::Color {
+        fragment.input().color
+    }
+}
@@ -26,7 +26,7 @@ fn main() -> anyhow::Result<()> {
 
     let vertex = |[x, y]: [f32; 2], [r, g, b]: [f32; 3]| Vertex {
         position: soft::Vector3 { x, y, z: 0.0 },
-        color: soft::Color { r, g, b },
+        color: soft::Color { r, g, b, a: 1.0 },
     };
     let vertices = vec![
         vertex([0.0, 0.5], [1.0, 0.0, 0.0]),
@@ -37,7 +37,7 @@ fn main() -> anyhow::Result<()> {
     let triangles = vec![[0, 1, 2].into()];
 
     while window.is_open() {
-        soft::draw(&mut buffer, &shaders, &vertices, &triangles);
+        soft::draw(&mut buffer, None, &shaders, &vertices, &triangles);
         window
             .update_with_buffer(
                 &buffer.pixels,
@@ -67,6 +67,11 @@ impl soft::PixelBuffer for ImageBuffer {
         self.size
     }
 
+    fn get(&self, pixel: soft::PixelCoord) -> soft::Color {
+        let index = pixel.x + pixel.y * self.size.width;
+        color_from_pixel(self.pixels[index as usize])
+    }
+
     fn set(&mut self, pixel: soft::PixelCoord, color: soft::Color) {
         assert!(pixel.x < self.size.width, "pixel out of bounds");
         assert!(pixel.y < self.size.height, "pixel out of bounds");
@@ -76,6 +81,11 @@ impl soft::PixelBuffer for ImageBuffer {
     }
 }
 
+fn color_from_pixel(pixel: Pixel) -> soft::Color {
+    let channel = |shift: u32| ((pixel >> shift) & 0xff) as f32 / 255.0;
+    [channel(16), channel(8), channel(0)].into()
+}
+
 fn pixel_from_color(color: soft::Color) -> Pixel {
     let r = (255.0 * color.r) as u8 as u32;
     let g = (255.0 * color.g) as u8 as u32;
@@ -83,7 +93,7 @@ fn pixel_from_color(color: soft::Color) -> Pixel {
     (r << 16) | (g << 8) | b
 }
 
-#[derive(soft::Interpolate)]
+#[derive(Clone, soft::Interpolate)]
 struct FragData {
     color: soft::Color,
 }
@@ -91,12 +101,13 @@ struct FragData {
 impl soft::ShaderModule for Shaders {
     type VertexInput = Vertex;
     type FragmentInput = FragData;
+    type Output = soft::Color;
 
     fn vertex_shader(&self, input: &Self::VertexInput) -> (soft::Vector4, Self::FragmentInput) {
         (input.position.extend(1.0), FragData { color: input.color })
     }
 
-    fn fragment_shader(&self, input: &Self::FragmentInput) -> soft::Color {
-        input.color
+    fn fragment_shader(&self, fragment: &soft::Fragment<Self::FragmentInput>) -> soft::Color {
+        fragment.input().color
     }
 }
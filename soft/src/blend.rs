@@ -0,0 +1,284 @@
+//! Blending of fragments with the colors already in the framebuffer.
+
+use crate::Color;
+
+/// How the color output by the fragment shader (the source) is combined with the color in the
+/// framebuffer (the destination). The RGB channels and the alpha channel are blended separately.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlendState {
+    pub color: BlendComponent,
+    pub alpha: BlendComponent,
+}
+
+/// Computes `equation(source * src_factor, destination * dst_factor)`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BlendComponent {
+    pub src_factor: BlendFactor,
+    pub dst_factor: BlendFactor,
+    pub equation: BlendEquation,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlendEquation {
+    /// `source + destination`
+    Add,
+    /// `source - destination`
+    Subtract,
+    /// `destination - source`
+    ReverseSubtract,
+    /// The smallest of the source and destination. The blend factors are ignored.
+    Min,
+    /// The largest of the source and destination. The blend factors are ignored.
+    Max,
+}
+
+/// Which channels of the framebuffer fragments are written to. The other channels keep their
+/// existing values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ColorWriteMask {
+    pub r: bool,
+    pub g: bool,
+    pub b: bool,
+    pub a: bool,
+}
+
+impl BlendState {
+    /// Overwrite the destination with the source.
+    pub const REPLACE: BlendState = BlendState::uniform(BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::Zero,
+        equation: BlendEquation::Add,
+    });
+
+    /// Traditional transparency: `source * source.a + destination * (1 - source.a)`.
+    pub const ALPHA: BlendState = BlendState {
+        color: BlendComponent {
+            src_factor: BlendFactor::SrcAlpha,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            equation: BlendEquation::Add,
+        },
+        alpha: BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            equation: BlendEquation::Add,
+        },
+    };
+
+    /// Transparency where the source color has already been multiplied by its alpha.
+    pub const PREMULTIPLIED_ALPHA: BlendState = BlendState::uniform(BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::OneMinusSrcAlpha,
+        equation: BlendEquation::Add,
+    });
+
+    /// Add the source to the destination, useful for particles and lights.
+    pub const ADDITIVE: BlendState = BlendState::uniform(BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        equation: BlendEquation::Add,
+    });
+
+    /// Use the same blend function for both the RGB and alpha channels.
+    pub const fn uniform(component: BlendComponent) -> BlendState {
+        BlendState {
+            color: component,
+            alpha: component,
+        }
+    }
+
+    pub fn blend(&self, source: Color, destination: Color) -> Color {
+        let [r, g, b, _] = self.color.apply(source, destination);
+        let [_, _, _, a] = self.alpha.apply(source, destination);
+        Color { r, g, b, a }
+    }
+}
+
+impl ColorWriteMask {
+    pub const ALL: ColorWriteMask = ColorWriteMask {
+        r: true,
+        g: true,
+        b: true,
+        a: true,
+    };
+
+    /// Only write the RGB channels, keeping the existing alpha.
+    pub const COLOR: ColorWriteMask = ColorWriteMask {
+        a: false,
+        ..Self::ALL
+    };
+
+    pub const NONE: ColorWriteMask = ColorWriteMask {
+        r: false,
+        g: false,
+        b: false,
+        a: false,
+    };
+
+    /// Take the channels in the mask from the source, and the rest from the destination.
+    #[inline]
+    pub fn apply(self, source: Color, destination: Color) -> Color {
+        let select = |write: bool, source: f32, destination: f32| {
+            if write {
+                source
+            } else {
+                destination
+            }
+        };
+        Color {
+            r: select(self.r, source.r, destination.r),
+            g: select(self.g, source.g, destination.g),
+            b: select(self.b, source.b, destination.b),
+            a: select(self.a, source.a, destination.a),
+        }
+    }
+}
+
+impl BlendComponent {
+    /// Blend all the channels `[r, g, b, a]` of the source and destination.
+    #[inline]
+    fn apply(&self, source: Color, destination: Color) -> [f32; 4] {
+        let src = source.to_arr();
+        let dst = destination.to_arr();
+        let src_factor = self.src_factor.factor(source, destination);
+        let dst_factor = self.dst_factor.factor(source, destination);
+
+        let mut output = [0.0; 4];
+        for i in 0..4 {
+            output[i] = match self.equation {
+                BlendEquation::Add => src[i] * src_factor[i] + dst[i] * dst_factor[i],
+                BlendEquation::Subtract => src[i] * src_factor[i] - dst[i] * dst_factor[i],
+                BlendEquation::ReverseSubtract => dst[i] * dst_factor[i] - src[i] * src_factor[i],
+                BlendEquation::Min => src[i].min(dst[i]),
+                BlendEquation::Max => src[i].max(dst[i]),
+            };
+        }
+        output
+    }
+}
+
+impl BlendFactor {
+    /// The factor for each of the channels `[r, g, b, a]`.
+    #[inline]
+    fn factor(self, source: Color, destination: Color) -> [f32; 4] {
+        let one_minus = |[r, g, b, a]: [f32; 4]| [1.0 - r, 1.0 - g, 1.0 - b, 1.0 - a];
+        match self {
+            BlendFactor::Zero => [0.0; 4],
+            BlendFactor::One => [1.0; 4],
+            BlendFactor::SrcColor => source.to_arr(),
+            BlendFactor::OneMinusSrcColor => one_minus(source.to_arr()),
+            BlendFactor::DstColor => destination.to_arr(),
+            BlendFactor::OneMinusDstColor => one_minus(destination.to_arr()),
+            BlendFactor::SrcAlpha => [source.a; 4],
+            BlendFactor::OneMinusSrcAlpha => [1.0 - source.a; 4],
+            BlendFactor::DstAlpha => [destination.a; 4],
+            BlendFactor::OneMinusDstAlpha => [1.0 - destination.a; 4],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::{DepthBuffer, PixelCoord, Rasterizer};
+
+    #[test]
+    fn alpha_blending() {
+        let source = Color::from([1.0, 0.0, 0.0, 0.25]);
+        let destination = Color::from([0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(
+            BlendState::ALPHA.blend(source, destination),
+            Color::from([0.25, 0.0, 0.75, 1.0])
+        );
+    }
+
+    #[test]
+    fn write_mask_keeps_masked_channels() {
+        let source = Color::from([1.0, 1.0, 1.0, 1.0]);
+        let destination = Color::from([0.0, 0.25, 0.5, 0.75]);
+        assert_eq!(ColorWriteMask::ALL.apply(source, destination), source);
+        assert_eq!(ColorWriteMask::NONE.apply(source, destination), destination);
+        assert_eq!(
+            ColorWriteMask::COLOR.apply(source, destination),
+            Color::from([1.0, 1.0, 1.0, 0.75])
+        );
+    }
+
+    #[test]
+    fn min_max_ignore_factors() {
+        let component = |equation| BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::Zero,
+            equation,
+        };
+        let source = Color::from([0.2, 0.8, 0.5, 1.0]);
+        let destination = Color::from([0.6, 0.4, 0.5, 0.0]);
+
+        let min = BlendState::uniform(component(BlendEquation::Min));
+        let max = BlendState::uniform(component(BlendEquation::Max));
+        assert_eq!(
+            min.blend(source, destination),
+            Color::from([0.2, 0.4, 0.5, 0.0])
+        );
+        assert_eq!(
+            max.blend(source, destination),
+            Color::from([0.6, 0.8, 0.5, 1.0])
+        );
+    }
+
+    #[test]
+    fn color_write_mask_keeps_masked_channels() {
+        let gray = Color::from([0.5, 0.5, 0.5, 0.5]);
+        let mut pixels = TestBuffer::new(4, 4);
+        pixels.pixels.iter_mut().for_each(|color| *color = gray);
+        let mut depth = DepthBuffer::new(pixels.size);
+        let rasterizer = Rasterizer {
+            color_write: ColorWriteMask {
+                g: true,
+                ..ColorWriteMask::NONE
+            },
+            ..Rasterizer::default()
+        };
+        let quad = quad(0.5, Color::WHITE);
+        rasterizer.draw(&mut pixels, Some(&mut depth), &FlatShaders, &quad, &QUAD);
+
+        for color in &pixels.pixels {
+            assert_eq!(*color, Color::from([0.5, 1.0, 0.5, 0.5]));
+        }
+        assert_eq!(depth.get(PixelCoord { x: 2, y: 1 }), 0.5);
+    }
+
+    #[test]
+    fn blending_is_set_per_draw() {
+        let mut pixels = TestBuffer::new(4, 4);
+
+        // the same shaders draw opaque first, then blended on top
+        let red = Color::from([1.0, 0.0, 0.0, 0.5]);
+        let blue = Color::from([0.0, 0.0, 1.0, 0.5]);
+        Rasterizer::default().draw(&mut pixels, None, &FlatShaders, &quad(0.5, red), &QUAD);
+        assert!(pixels.pixels.iter().all(|&color| color == red));
+
+        let blended = Rasterizer {
+            blend: Some(BlendState::ALPHA),
+            ..Rasterizer::default()
+        };
+        blended.draw(&mut pixels, None, &FlatShaders, &quad(0.5, blue), &QUAD);
+        let expected = BlendState::ALPHA.blend(blue, red);
+        assert!(pixels.pixels.iter().all(|&color| color == expected));
+        assert_eq!((expected.r, expected.b), (0.5, 0.5));
+    }
+}
@@ -0,0 +1,256 @@
+//! Clipping of triangles, lines and points against the view frustum in homogeneous clip space.
+
+use crate::matrix::Vector4;
+
+/// Smallest `w` a vertex may have after clipping, avoids dividing by zero in the perspective
+/// divide.
+const W_EPSILON: f32 = 1e-5;
+
+/// The planes that bound the visible volume in clip space. A point is inside a plane when
+/// `plane.dot(point) + offset >= 0`.
+const PLANES: [(Vector4, f32); 7] = [
+    (Vector4::new(1.0, 0.0, 0.0, 1.0), 0.0),
+    (Vector4::new(-1.0, 0.0, 0.0, 1.0), 0.0),
+    (Vector4::new(0.0, 1.0, 0.0, 1.0), 0.0),
+    (Vector4::new(0.0, -1.0, 0.0, 1.0), 0.0),
+    (Vector4::new(0.0, 0.0, 1.0, 0.0), 0.0),
+    (Vector4::new(0.0, 0.0, -1.0, 1.0), 0.0),
+    (Vector4::new(0.0, 0.0, 0.0, 1.0), -W_EPSILON),
+];
+
+/// Every plane may add at most one vertex to the polygon.
+const MAX_VERTICES: usize = 3 + PLANES.len();
+
+/// A vertex produced by clipping. Its attributes are expressed as barycentric weights of the
+/// vertices in the original triangle, so that they can be interpolated using
+/// [`Interpolate`](crate::Interpolate) without cloning.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct ClipVertex {
+    pub position: Vector4,
+    pub weights: [f32; 3],
+}
+
+/// A convex polygon that lies entirely within the visible volume.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Polygon {
+    vertices: [ClipVertex; MAX_VERTICES],
+    len: usize,
+}
+
+pub(crate) enum Clipped {
+    /// The triangle is entirely within the visible volume.
+    Inside,
+    /// The triangle is entirely outside the visible volume.
+    Outside,
+    /// The triangle intersects the bounds of the visible volume.
+    Polygon(Box<Polygon>),
+}
+
+/// Clip a triangle (with positions in clip space) against the bounds of the visible volume.
+pub(crate) fn clip_triangle(positions: [Vector4; 3]) -> Clipped {
+    let mut outside_any = 0u32;
+    let mut outside_all = u32::MAX;
+    for position in &positions {
+        let outcode = outcode(*position);
+        outside_any |= outcode;
+        outside_all &= outcode;
+    }
+
+    if outside_any == 0 {
+        return Clipped::Inside;
+    }
+    if outside_all != 0 {
+        return Clipped::Outside;
+    }
+
+    let mut polygon = Polygon::from_triangle(positions);
+    for (i, plane) in PLANES.iter().enumerate() {
+        if outside_any & (1 << i) != 0 {
+            polygon = polygon.clip(*plane);
+            if polygon.len < 3 {
+                return Clipped::Outside;
+            }
+        }
+    }
+
+    Clipped::Polygon(Box::new(polygon))
+}
+
+/// Clip a line (with positions in clip space) against the bounds of the visible volume, returning
+/// the end points of the visible part of the line. The weights of the end points refer to the
+/// first two vertices of the original line.
+pub(crate) fn clip_line([a, b]: [Vector4; 2]) -> Option<[ClipVertex; 2]> {
+    // Liang-Barsky: shrink the range of the line parameter `t` to the inside of every plane
+    let (mut t_min, mut t_max) = (0.0f32, 1.0f32);
+    for plane in PLANES {
+        let (distance_a, distance_b) = (distance(plane, a), distance(plane, b));
+        if distance_a < 0.0 && distance_b < 0.0 {
+            return None;
+        }
+        if distance_a < 0.0 {
+            t_min = t_min.max(distance_a / (distance_a - distance_b));
+        } else if distance_b < 0.0 {
+            t_max = t_max.min(distance_a / (distance_a - distance_b));
+        }
+    }
+
+    if t_min > t_max {
+        return None;
+    }
+
+    let vertex = |t: f32| ClipVertex {
+        position: a + t * (b - a),
+        weights: [1.0 - t, t, 0.0],
+    };
+    Some([vertex(t_min), vertex(t_max)])
+}
+
+/// Points are either drawn in full or not at all, depending on if they are inside the visible
+/// volume.
+pub(crate) fn point_visible(position: Vector4) -> bool {
+    outcode(position) == 0
+}
+
+/// Get a bitmask of the planes the point is outside of.
+fn outcode(position: Vector4) -> u32 {
+    let mut code = 0;
+    for (i, plane) in PLANES.iter().enumerate() {
+        if distance(*plane, position) < 0.0 {
+            code |= 1 << i;
+        }
+    }
+    code
+}
+
+#[inline(always)]
+fn distance((plane, offset): (Vector4, f32), position: Vector4) -> f32 {
+    plane.dot(position) + offset
+}
+
+impl Polygon {
+    fn from_triangle([a, b, c]: [Vector4; 3]) -> Polygon {
+        let vertex = |position, weights| ClipVertex { position, weights };
+        let mut polygon = Polygon::empty();
+        polygon.push(vertex(a, [1.0, 0.0, 0.0]));
+        polygon.push(vertex(b, [0.0, 1.0, 0.0]));
+        polygon.push(vertex(c, [0.0, 0.0, 1.0]));
+        polygon
+    }
+
+    fn empty() -> Polygon {
+        Polygon {
+            vertices: [ClipVertex {
+                position: Vector4::ORIGIN,
+                weights: [0.0; 3],
+            }; MAX_VERTICES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, vertex: ClipVertex) {
+        self.vertices[self.len] = vertex;
+        self.len += 1;
+    }
+
+    /// Sutherland-Hodgman clipping against a single plane.
+    fn clip(&self, plane: (Vector4, f32)) -> Polygon {
+        let mut output = Polygon::empty();
+
+        for i in 0..self.len {
+            let current = self.vertices[i];
+            let next = self.vertices[(i + 1) % self.len];
+
+            let current_distance = distance(plane, current.position);
+            let next_distance = distance(plane, next.position);
+
+            if current_distance >= 0.0 {
+                output.push(current);
+            }
+
+            if (current_distance >= 0.0) != (next_distance >= 0.0) {
+                let t = current_distance / (current_distance - next_distance);
+                output.push(ClipVertex {
+                    position: current.position + t * (next.position - current.position),
+                    weights: [
+                        current.weights[0] + t * (next.weights[0] - current.weights[0]),
+                        current.weights[1] + t * (next.weights[1] - current.weights[1]),
+                        current.weights[2] + t * (next.weights[2] - current.weights[2]),
+                    ],
+                });
+            }
+        }
+
+        output
+    }
+
+    /// Split the polygon into a fan of triangles.
+    pub fn triangles(&self) -> impl Iterator<Item = [ClipVertex; 3]> + '_ {
+        (1..self.len.saturating_sub(1))
+            .map(move |i| [self.vertices[0], self.vertices[i], self.vertices[i + 1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangle_inside_is_untouched() {
+        let positions = [
+            Vector4::new(-0.5, -0.5, 0.5, 1.0),
+            Vector4::new(0.5, -0.5, 0.5, 1.0),
+            Vector4::new(0.0, 0.5, 0.5, 1.0),
+        ];
+        assert!(matches!(clip_triangle(positions), Clipped::Inside));
+    }
+
+    #[test]
+    fn triangle_behind_camera_is_culled() {
+        let positions = [
+            Vector4::new(-0.5, -0.5, -1.0, -1.0),
+            Vector4::new(0.5, -0.5, -1.0, -1.0),
+            Vector4::new(0.0, 0.5, -1.0, -1.0),
+        ];
+        assert!(matches!(clip_triangle(positions), Clipped::Outside));
+    }
+
+    #[test]
+    fn triangle_crossing_near_plane_is_clipped() {
+        let positions = [
+            Vector4::new(0.0, 0.0, -1.0, 1.0),
+            Vector4::new(0.5, 0.0, 1.0, 2.0),
+            Vector4::new(0.0, 0.5, 1.0, 2.0),
+        ];
+
+        let polygon = match clip_triangle(positions) {
+            Clipped::Polygon(polygon) => polygon,
+            _ => panic!("expected the triangle to be clipped"),
+        };
+
+        assert_eq!(polygon.triangles().count(), 2);
+        for vertex in polygon.triangles().flatten() {
+            assert!(vertex.position.z >= -1e-6);
+            assert!(vertex.position.w > 0.0);
+
+            let weights = vertex.weights;
+            let expected =
+                positions[0] * weights[0] + positions[1] * weights[1] + positions[2] * weights[2];
+            assert!((expected - vertex.position).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn line_is_clipped_to_visible_part() {
+        let positions = [
+            Vector4::new(-2.0, 0.0, 0.5, 1.0),
+            Vector4::new(0.5, 0.0, 0.5, 1.0),
+        ];
+        let [start, end] = clip_line(positions).unwrap();
+        assert!((start.position - Vector4::new(-1.0, 0.0, 0.5, 1.0)).length() < 1e-6);
+        assert!((start.weights[1] - 0.4).abs() < 1e-6);
+        assert_eq!(end.position, positions[1]);
+
+        let behind = positions.map(|position| Vector4::new(position.x, 0.0, -1.0, 1.0));
+        assert!(clip_line(behind).is_none());
+    }
+}
@@ -0,0 +1,383 @@
+//! An in-memory framebuffer that can be saved to image files, for rendering without a window,
+//! and loaded from them, for use as textures.
+
+mod bmp;
+mod png;
+mod ppm;
+mod tga;
+mod zlib;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::{Color, Dimensions, PixelBuffer, PixelCoord, Texture};
+
+/// A framebuffer storing the full floating point color of every pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    size: Dimensions,
+    pixels: Vec<Color>,
+}
+
+/// The file formats images can be read from and written as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    /// Netpbm pixmaps and graymaps. Reads the ASCII and binary variants of PPM and PGM, and
+    /// writes binary PPM (`P6`), dropping the alpha channel.
+    Ppm,
+    /// Truevision TGA. Reads uncompressed and run-length encoded true-color and grayscale images,
+    /// and writes uncompressed 32-bit images.
+    Tga,
+    /// Windows bitmaps. Reads uncompressed 1, 4, 8, 16, 24 and 32-bit images, and writes 32-bit
+    /// images.
+    Bmp,
+    /// Portable Network Graphics. Reads all standard color types and bit depths, and writes 8-bit
+    /// RGBA images.
+    Png,
+}
+
+/// The reasons an image could not be loaded.
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// The format of the image could not be determined.
+    UnknownFormat,
+    /// The image uses a feature of its format that is not supported.
+    Unsupported(String),
+    /// The image is not valid according to its format.
+    Malformed(String),
+}
+
+impl Image {
+    /// Create a new image where every pixel is transparent.
+    pub fn new(size: Dimensions) -> Image {
+        Image::from_pixels(
+            size,
+            vec![Color::TRANSPARENT; size.width as usize * size.height as usize],
+        )
+    }
+
+    /// Create an image from pixels stored in rows from top to bottom.
+    pub fn from_pixels(size: Dimensions, pixels: Vec<Color>) -> Image {
+        assert_eq!(pixels.len(), size.width as usize * size.height as usize);
+        Image { size, pixels }
+    }
+
+    /// The pixels of the image, in rows from top to bottom.
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Set the color of every pixel in the image
+    pub fn clear(&mut self, color: Color) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = color);
+    }
+
+    /// Load an image from a file. The format is determined from the contents of the file, falling
+    /// back to the extension of the path for formats without a signature.
+    pub fn load(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        let format = ImageFormat::from_signature(&data)
+            .or_else(|| ImageFormat::from_path(path))
+            .ok_or(ImageError::UnknownFormat)?;
+        Image::decode(&data, format)
+    }
+
+    /// Decode an image stored in a specific format.
+    pub fn decode(data: &[u8], format: ImageFormat) -> Result<Image, ImageError> {
+        match format {
+            ImageFormat::Ppm => ppm::read(data),
+            ImageFormat::Tga => tga::read(data),
+            ImageFormat::Bmp => bmp::read(data),
+            ImageFormat::Png => png::read(data),
+        }
+    }
+
+    /// The luminance of every pixel, in rows from top to bottom.
+    pub fn to_luminance(&self) -> Vec<f32> {
+        self.pixels.iter().map(|color| luminance(*color)).collect()
+    }
+
+    /// The pixels of the image as 8-bit `[r, g, b, a]`, clamping the channels to `[0, 1]`.
+    pub fn to_rgba8(&self) -> Vec<[u8; 4]> {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        self.pixels
+            .iter()
+            .map(|color| color.to_arr().map(channel))
+            .collect()
+    }
+
+    /// Save the image to a file, in the format given by the extension of the path.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown image format: {}", path.display()),
+            )
+        })?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, format)?;
+        writer.flush()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, format: ImageFormat) -> io::Result<()> {
+        match format {
+            ImageFormat::Ppm => ppm::write(self, writer),
+            ImageFormat::Tga => tga::write(self, writer),
+            ImageFormat::Bmp => bmp::write(self, writer),
+            ImageFormat::Png => png::write(self, writer),
+        }
+    }
+
+    #[inline(always)]
+    fn index(&self, pixel: PixelCoord) -> usize {
+        debug_assert!(pixel.x < self.size.width, "pixel out of bounds");
+        debug_assert!(pixel.y < self.size.height, "pixel out of bounds");
+        pixel.x as usize + pixel.y as usize * self.size.width as usize
+    }
+}
+
+impl PixelBuffer for Image {
+    fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    fn get(&self, pixel: PixelCoord) -> Color {
+        self.pixels[self.index(pixel)]
+    }
+
+    fn set(&mut self, pixel: PixelCoord, color: Color) {
+        let index = self.index(pixel);
+        self.pixels[index] = color;
+    }
+}
+
+impl ImageFormat {
+    /// Guess the format from the extension of a path.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" | "pgm" | "pnm" => Some(ImageFormat::Ppm),
+            "tga" => Some(ImageFormat::Tga),
+            "bmp" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the first bytes of a file. TGA files have no signature, and are
+    /// never detected.
+    pub fn from_signature(data: &[u8]) -> Option<ImageFormat> {
+        match data {
+            [0x89, b'P', b'N', b'G', ..] => Some(ImageFormat::Png),
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            [b'P', b'2'..=b'3', ..] | [b'P', b'5'..=b'6', ..] => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+impl From<Image> for Texture<Color> {
+    fn from(image: Image) -> Self {
+        Texture::new(image.pixels, image.size)
+    }
+}
+
+impl Texture<Color> {
+    /// Load a texture from an image file, see [`Image::load`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Texture<Color>, ImageError> {
+        Image::load(path).map(Texture::from)
+    }
+}
+
+impl Texture<f32> {
+    /// Load a texture from an image file, storing the luminance of every pixel. Useful for
+    /// grayscale images such as height maps.
+    pub fn load_luminance(path: impl AsRef<Path>) -> Result<Texture<f32>, ImageError> {
+        let image = Image::load(path)?;
+        Ok(Texture::new(image.to_luminance(), image.size))
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(error) => write!(f, "{}", error),
+            ImageError::UnknownFormat => write!(f, "unknown image format"),
+            ImageError::Unsupported(feature) => write!(f, "unsupported image: {}", feature),
+            ImageError::Malformed(reason) => write!(f, "malformed image: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImageError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(error: io::Error) -> Self {
+        ImageError::Io(error)
+    }
+}
+
+/// Relative luminance of a color, with the weights of Rec. 709. Grays are returned exactly, so
+/// that grayscale images load without rounding errors.
+fn luminance(color: Color) -> f32 {
+    if color.r == color.g && color.g == color.b {
+        return color.r;
+    }
+    0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+/// Reads values from the bytes of an image file.
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Bytes<'a> {
+        Bytes { data, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ImageError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|&end| end <= self.data.len());
+        let bytes = &self.data[self.position..end.ok_or_else(unexpected_end)?];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), ImageError> {
+        self.take(count).map(|_| ())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.position).copied()
+    }
+
+    fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16_le(&mut self) -> Result<u16, ImageError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_le(&mut self) -> Result<u32, ImageError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u32_be(&mut self) -> Result<u32, ImageError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+fn malformed(reason: impl Into<String>) -> ImageError {
+    ImageError::Malformed(reason.into())
+}
+
+fn unsupported(feature: impl Into<String>) -> ImageError {
+    ImageError::Unsupported(feature.into())
+}
+
+fn unexpected_end() -> ImageError {
+    malformed("unexpected end of file")
+}
+
+/// Check that the image is not empty, and not so large that its pixels cannot be allocated.
+fn checked_size(width: u32, height: u32) -> Result<Dimensions, ImageError> {
+    if width == 0 || height == 0 {
+        return Err(malformed("image has no pixels"));
+    }
+    if width as u64 * height as u64 > 1 << 28 {
+        return Err(unsupported(format!("image size {}x{}", width, height)));
+    }
+    Ok(Dimensions { width, height })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(ImageFormat::from_path("out.PNG"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("a/b.tga"), Some(ImageFormat::Tga));
+        assert_eq!(ImageFormat::from_path("image.ppm"), Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path("height.pgm"), Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path("image.bmp"), Some(ImageFormat::Bmp));
+        assert_eq!(ImageFormat::from_path("image.jpg"), None);
+        assert_eq!(ImageFormat::from_path("image"), None);
+    }
+
+    #[test]
+    fn round_trip_through_every_format() {
+        let pixels = (0..12)
+            .map(|i| Color::from([i as f32 / 11.0, 1.0 - i as f32 / 11.0, 0.2, 1.0]))
+            .collect();
+        let image = Image::from_pixels([4, 3].into(), pixels);
+        let formats = [
+            ImageFormat::Ppm,
+            ImageFormat::Tga,
+            ImageFormat::Bmp,
+            ImageFormat::Png,
+        ];
+
+        for format in formats {
+            let mut data = Vec::new();
+            image.write(&mut data, format).unwrap();
+            let decoded = Image::decode(&data, format).unwrap();
+            assert_eq!(decoded.size(), image.size(), "{:?}", format);
+            assert_eq!(decoded.to_rgba8(), image.to_rgba8(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn truncated_files_are_malformed() {
+        let image = Image::from_pixels([2, 2].into(), vec![Color::WHITE; 4]);
+        let formats = [
+            ImageFormat::Ppm,
+            ImageFormat::Tga,
+            ImageFormat::Bmp,
+            ImageFormat::Png,
+        ];
+
+        for format in formats {
+            let mut data = Vec::new();
+            image.write(&mut data, format).unwrap();
+            for len in [0, 5, data.len() - 1] {
+                assert!(
+                    matches!(
+                        Image::decode(&data[..len], format),
+                        Err(ImageError::Malformed(_))
+                    ),
+                    "{:?} truncated to {} bytes",
+                    format,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn channels_are_clamped_and_rounded() {
+        let image = Image::from_pixels([1, 1].into(), vec![Color::from([-1.0, 0.5, 2.0, 1.0])]);
+        assert_eq!(image.to_rgba8(), [[0, 128, 255, 255]]);
+    }
+}
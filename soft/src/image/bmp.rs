@@ -0,0 +1,282 @@
+//! Windows bitmaps, see
+//! <https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-storage>.
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use super::{checked_size, malformed, unsupported, Bytes, ImageError};
+use crate::{Color, Image, PixelBuffer};
+
+const FILE_HEADER_SIZE: u32 = 14;
+
+/// Size of the `BITMAPCOREHEADER`, which predates the other headers.
+const CORE_HEADER_SIZE: u32 = 12;
+/// Size of the `BITMAPINFOHEADER`.
+const INFO_HEADER_SIZE: u32 = 40;
+/// Size of the `BITMAPV4HEADER`.
+const V4_HEADER_SIZE: u32 = 108;
+
+/// Compression of images where the pixels are stored as is.
+const BI_RGB: u32 = 0;
+/// Compression of images where the channels of the pixels are given by bit masks.
+const BI_BITFIELDS: u32 = 3;
+/// Like `BI_BITFIELDS`, but with an additional mask for the alpha channel.
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// The `sRGB` color space, as the four character code stored in the V4 header.
+const LCS_SRGB: u32 = 0x7352_4742;
+
+pub(super) fn read(data: &[u8]) -> Result<Image, ImageError> {
+    let mut bytes = Bytes::new(data);
+    if bytes.take(2)? != b"BM" {
+        return Err(malformed("missing bitmap signature"));
+    }
+    bytes.skip(8)?;
+    let data_offset = bytes.u32_le()? as usize;
+
+    let header_size = bytes.u32_le()?;
+    let (width, height, bits, compression, colors_used) = match header_size {
+        CORE_HEADER_SIZE => {
+            let width = bytes.u16_le()? as i32;
+            let height = bytes.u16_le()? as i32;
+            bytes.skip(2)?;
+            (width, height, bytes.u16_le()?, BI_RGB, 0)
+        }
+        INFO_HEADER_SIZE.. => {
+            let width = bytes.u32_le()? as i32;
+            let height = bytes.u32_le()? as i32;
+            bytes.skip(2)?;
+            let bits = bytes.u16_le()?;
+            let compression = bytes.u32_le()?;
+            bytes.skip(12)?;
+            let colors_used = bytes.u32_le()?;
+            bytes.skip(4)?;
+            (width, height, bits, compression, colors_used)
+        }
+        _ => {
+            return Err(unsupported(format!(
+                "bitmap header of {} bytes",
+                header_size
+            )))
+        }
+    };
+
+    // the masks directly follow the info header, but are part of the later headers
+    let masks = match compression {
+        BI_RGB => match bits {
+            16 => [0x7c00, 0x03e0, 0x001f, 0],
+            _ => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0],
+        },
+        BI_BITFIELDS | BI_ALPHABITFIELDS => {
+            let alpha = compression == BI_ALPHABITFIELDS || header_size >= 56;
+            let mut masks = [bytes.u32_le()?, bytes.u32_le()?, bytes.u32_le()?, 0];
+            if alpha {
+                masks[3] = bytes.u32_le()?;
+            }
+            masks
+        }
+        _ => return Err(unsupported("compressed bitmap")),
+    };
+
+    let top_down = height < 0;
+    let size = checked_size(width.unsigned_abs(), height.unsigned_abs())?;
+    if width < 0 {
+        return Err(malformed("negative bitmap width"));
+    }
+
+    let palette = if bits <= 8 {
+        let header_end = FILE_HEADER_SIZE as usize + header_size as usize;
+        let entry_size = if header_size == CORE_HEADER_SIZE {
+            3
+        } else {
+            4
+        };
+        let count = match colors_used {
+            0 => 1 << bits,
+            count => count as usize,
+        };
+        let palette = data
+            .get(header_end..)
+            .and_then(|palette| palette.get(..count.checked_mul(entry_size)?))
+            .ok_or_else(|| malformed("palette out of bounds"))?;
+        palette
+            .chunks_exact(entry_size)
+            .map(|entry| Color::from([channel(entry[2]), channel(entry[1]), channel(entry[0])]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let stride = (bits as usize * size.width as usize).div_ceil(32) * 4;
+    let raster = data
+        .get(data_offset..)
+        .and_then(|raster| raster.get(..stride * size.height as usize))
+        .ok_or_else(|| malformed("unexpected end of file"))?;
+
+    let mut pixels = Vec::with_capacity(size.width as usize * size.height as usize);
+    for y in 0..size.height as usize {
+        let row = if top_down {
+            y
+        } else {
+            size.height as usize - 1 - y
+        };
+        let row = &raster[row * stride..][..stride];
+
+        for x in 0..size.width as usize {
+            let color = match bits {
+                1 | 2 | 4 | 8 => {
+                    let bit = x * bits as usize;
+                    let shift = 8 - bits as usize - bit % 8;
+                    let index = (row[bit / 8] >> shift) & ((1 << bits) - 1) as u8;
+                    *palette
+                        .get(index as usize)
+                        .ok_or_else(|| malformed("palette index out of bounds"))?
+                }
+                16 => masked(
+                    u16::from_le_bytes([row[2 * x], row[2 * x + 1]]) as u32,
+                    masks,
+                ),
+                24 => Color::from([
+                    channel(row[3 * x + 2]),
+                    channel(row[3 * x + 1]),
+                    channel(row[3 * x]),
+                ]),
+                32 => {
+                    let pixel = &row[4 * x..][..4];
+                    masked(
+                        u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]),
+                        masks,
+                    )
+                }
+                _ => return Err(unsupported(format!("{}-bit bitmap", bits))),
+            };
+            pixels.push(color);
+        }
+    }
+
+    Ok(Image::from_pixels(size, pixels))
+}
+
+pub(super) fn write<W: Write>(image: &Image, writer: &mut W) -> io::Result<()> {
+    let size = image.size();
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "image is too large for BMP");
+    let width = i32::try_from(size.width).map_err(|_| too_large())?;
+    let height = i32::try_from(size.height).map_err(|_| too_large())?;
+    let data_offset = FILE_HEADER_SIZE + V4_HEADER_SIZE;
+    let data_size = u32::try_from(size.width as u64 * size.height as u64 * 4)
+        .ok()
+        .and_then(|size| size.checked_add(data_offset))
+        .ok_or_else(too_large)?;
+
+    let mut header = Vec::with_capacity(data_offset as usize);
+    header.extend_from_slice(b"BM");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&data_offset.to_le_bytes());
+
+    header.extend_from_slice(&V4_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&width.to_le_bytes());
+    // a negative height stores the rows from the top down
+    header.extend_from_slice(&(-height).to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&32u16.to_le_bytes());
+    header.extend_from_slice(&BI_BITFIELDS.to_le_bytes());
+    header.extend_from_slice(&(data_size - data_offset).to_le_bytes());
+    // resolution, and number of colors in the palette
+    header.extend_from_slice(&[0; 16]);
+    for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+        header.extend_from_slice(&mask.to_le_bytes());
+    }
+    header.extend_from_slice(&LCS_SRGB.to_le_bytes());
+    // color space endpoints and gamma, unused for sRGB
+    header.extend_from_slice(&[0; 48]);
+    writer.write_all(&header)?;
+
+    let data = image
+        .to_rgba8()
+        .iter()
+        .flat_map(|&[r, g, b, a]| [b, g, r, a])
+        .collect::<Vec<u8>>();
+    writer.write_all(&data)
+}
+
+/// Extract the channels of a pixel given the masks of the `[r, g, b, a]` channels. Without an
+/// alpha mask the pixel is opaque.
+fn masked(pixel: u32, masks: [u32; 4]) -> Color {
+    let extract = |mask: u32| {
+        if mask == 0 {
+            return 0.0;
+        }
+        let max = mask >> mask.trailing_zeros();
+        ((pixel & mask) >> mask.trailing_zeros()) as f32 / max as f32
+    };
+    let alpha = if masks[3] == 0 {
+        1.0
+    } else {
+        extract(masks[3])
+    };
+    Color::from([
+        extract(masks[0]),
+        extract(masks[1]),
+        extract(masks[2]),
+        alpha,
+    ])
+}
+
+fn channel(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bitmap with an info header and the given bits per pixel, palette and rows.
+    fn bitmap(width: i32, height: i32, bits: u16, palette: &[[u8; 4]], raster: &[u8]) -> Vec<u8> {
+        let data_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + 4 * palette.len() as u32;
+        let mut data = b"BM".to_vec();
+        data.extend_from_slice(&(data_offset + raster.len() as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&data_offset.to_le_bytes());
+        data.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&bits.to_le_bytes());
+        data.extend_from_slice(&BI_RGB.to_le_bytes());
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend(palette.iter().flatten());
+        data.extend_from_slice(raster);
+        data
+    }
+
+    #[test]
+    fn palette_rows_are_padded_and_bottom_up() {
+        let palette = [[0, 0, 0, 0], [255, 255, 255, 0]];
+        // 1 bit per pixel, every row padded to 4 bytes
+        let raster = [0b1000_0000, 0, 0, 0, 0b0100_0000, 0, 0, 0];
+        let image = read(&bitmap(2, 2, 1, &palette, &raster)).unwrap();
+        assert_eq!(
+            image.pixels(),
+            [Color::BLACK, Color::WHITE, Color::WHITE, Color::BLACK]
+        );
+    }
+
+    #[test]
+    fn twenty_four_bit_top_down() {
+        // every row padded to 8 bytes
+        let raster = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0];
+        let image = read(&bitmap(2, -2, 24, &[], &raster)).unwrap();
+        assert_eq!(
+            image.to_rgba8(),
+            [
+                [255, 0, 0, 255],
+                [0, 255, 0, 255],
+                [0, 0, 255, 255],
+                [0, 0, 0, 255]
+            ]
+        );
+    }
+}
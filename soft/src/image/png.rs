@@ -0,0 +1,494 @@
+//! PNG images, see <https://www.w3.org/TR/png/>. Image data is written in uncompressed deflate
+//! blocks, which keeps the encoder small at the expense of larger files.
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use super::{checked_size, malformed, unsupported, zlib, Bytes, ImageError};
+use crate::{Color, Dimensions, Image, PixelBuffer};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_TYPE_GRAY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_GRAY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// The passes of Adam7 interlacing, as `(x, y, step_x, step_y)`.
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// The layout of the pixels in an image, given by its header.
+struct Format {
+    size: Dimensions,
+    bit_depth: u8,
+    color_type: u8,
+    palette: Vec<Color>,
+    /// The gray or RGB value of transparent pixels, in images without an alpha channel.
+    transparent: Option<[u16; 3]>,
+    interlaced: bool,
+}
+
+pub(super) fn read(data: &[u8]) -> Result<Image, ImageError> {
+    let mut bytes = Bytes::new(data);
+    if bytes.take(SIGNATURE.len())? != SIGNATURE {
+        return Err(malformed("missing PNG signature"));
+    }
+
+    let mut format = None;
+    let mut compressed = Vec::new();
+    loop {
+        let length = bytes.u32_be()? as usize;
+        let kind = bytes.take(4)?;
+        let chunk = bytes.take(length)?;
+        let crc = bytes.u32_be()?;
+        if !crc32_update(crc32_update(!0, kind), chunk) != crc {
+            return Err(malformed(format!(
+                "checksum mismatch in {} chunk",
+                String::from_utf8_lossy(kind)
+            )));
+        }
+
+        match (kind, &mut format) {
+            (b"IHDR", None) => format = Some(read_header(chunk)?),
+            (_, None) => return Err(malformed("missing IHDR chunk")),
+            (b"PLTE", Some(format)) => {
+                format.palette = chunk
+                    .chunks_exact(3)
+                    .map(|rgb| Color::from([channel(rgb[0]), channel(rgb[1]), channel(rgb[2])]))
+                    .collect();
+            }
+            (b"tRNS", Some(format)) => read_transparency(format, chunk)?,
+            (b"IDAT", Some(_)) => compressed.extend_from_slice(chunk),
+            (b"IEND", Some(_)) => break,
+            // ancillary chunks start with a lowercase letter, and may be ignored
+            (kind, Some(_)) if kind[0].is_ascii_lowercase() => {}
+            (kind, Some(_)) => {
+                return Err(unsupported(format!(
+                    "PNG chunk {}",
+                    String::from_utf8_lossy(kind)
+                )))
+            }
+        }
+    }
+
+    let format = format.ok_or_else(|| malformed("missing IHDR chunk"))?;
+    if format.color_type == COLOR_TYPE_PALETTE && format.palette.is_empty() {
+        return Err(malformed("missing PLTE chunk"));
+    }
+
+    let passes: &[_] = if format.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+
+    // the image data is never larger than the scanlines, which limits the memory used by
+    // malicious images
+    let expected_length = passes
+        .iter()
+        .map(|&pass| match format.pass_layout(pass) {
+            (0, _, _) => 0,
+            (_, height, stride) => (stride + 1) * height as usize,
+        })
+        .sum();
+    let scanlines = zlib::decompress(&compressed, expected_length)?;
+    let size = format.size;
+    let mut pixels = vec![Color::TRANSPARENT; size.width as usize * size.height as usize];
+
+    let mut remaining = &scanlines[..];
+    for &(x0, y0, step_x, step_y) in passes {
+        let (width, height, stride) = format.pass_layout((x0, y0, step_x, step_y));
+        if width == 0 || height == 0 {
+            continue;
+        }
+
+        let length = (stride + 1) * height as usize;
+        let pass = remaining
+            .get(..length)
+            .ok_or_else(|| malformed("not enough image data"))?;
+        remaining = &remaining[length..];
+
+        let rows = unfilter(pass, stride, format.bytes_per_pixel())?;
+        for (y, row) in rows.chunks_exact(stride).enumerate() {
+            for x in 0..width as usize {
+                let pixel_x = x0 as usize + x * step_x as usize;
+                let pixel_y = y0 as usize + y * step_y as usize;
+                pixels[pixel_x + pixel_y * size.width as usize] = format.pixel(row, x)?;
+            }
+        }
+    }
+
+    Ok(Image::from_pixels(size, pixels))
+}
+
+fn read_header(chunk: &[u8]) -> Result<Format, ImageError> {
+    let mut bytes = Bytes::new(chunk);
+    let width = bytes.u32_be()?;
+    let height = bytes.u32_be()?;
+    let bit_depth = bytes.u8()?;
+    let color_type = bytes.u8()?;
+    let compression = bytes.u8()?;
+    let filter = bytes.u8()?;
+    let interlace = bytes.u8()?;
+
+    let valid_depths: &[u8] = match color_type {
+        COLOR_TYPE_GRAY => &[1, 2, 4, 8, 16],
+        COLOR_TYPE_PALETTE => &[1, 2, 4, 8],
+        COLOR_TYPE_RGB | COLOR_TYPE_GRAY_ALPHA | COLOR_TYPE_RGBA => &[8, 16],
+        _ => return Err(malformed(format!("invalid color type {}", color_type))),
+    };
+    if !valid_depths.contains(&bit_depth) {
+        return Err(malformed(format!(
+            "invalid bit depth {} for color type {}",
+            bit_depth, color_type
+        )));
+    }
+    if compression != 0 || filter != 0 || interlace > 1 {
+        return Err(unsupported("PNG compression, filter or interlace method"));
+    }
+
+    Ok(Format {
+        size: checked_size(width, height)?,
+        bit_depth,
+        color_type,
+        palette: Vec::new(),
+        transparent: None,
+        interlaced: interlace == 1,
+    })
+}
+
+fn read_transparency(format: &mut Format, chunk: &[u8]) -> Result<(), ImageError> {
+    let mut bytes = Bytes::new(chunk);
+    let mut sample = || -> Result<u16, ImageError> {
+        let sample = bytes.take(2)?;
+        Ok(u16::from_be_bytes([sample[0], sample[1]]))
+    };
+
+    match format.color_type {
+        COLOR_TYPE_GRAY => {
+            let gray = sample()?;
+            format.transparent = Some([gray; 3]);
+        }
+        COLOR_TYPE_RGB => format.transparent = Some([sample()?, sample()?, sample()?]),
+        COLOR_TYPE_PALETTE => {
+            for (color, &alpha) in format.palette.iter_mut().zip(chunk) {
+                color.a = channel(alpha);
+            }
+        }
+        _ => return Err(malformed("tRNS chunk in image with an alpha channel")),
+    }
+    Ok(())
+}
+
+/// Reverse the filtering of scanlines, where every row starts with its filter type.
+fn unfilter(data: &[u8], stride: usize, bytes_per_pixel: usize) -> Result<Vec<u8>, ImageError> {
+    let height = data.len() / (stride + 1);
+    let mut rows = vec![0u8; stride * height];
+    let zeros = vec![0u8; stride];
+
+    for (y, filtered) in data.chunks_exact(stride + 1).enumerate() {
+        let (previous, current) = rows.split_at_mut(y * stride);
+        let above = match y {
+            0 => &zeros[..],
+            _ => &previous[(y - 1) * stride..],
+        };
+        let row = &mut current[..stride];
+        let filter = filtered[0];
+        row.copy_from_slice(&filtered[1..]);
+
+        for i in 0..stride {
+            let left = if i >= bytes_per_pixel {
+                row[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let upper_left = if i >= bytes_per_pixel {
+                above[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => above[i],
+                3 => ((left as u16 + above[i] as u16) / 2) as u8,
+                4 => paeth(left, above[i], upper_left),
+                _ => return Err(malformed(format!("invalid filter type {}", filter))),
+            };
+            row[i] = row[i].wrapping_add(predicted);
+        }
+    }
+
+    Ok(rows)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+impl Format {
+    /// The width and height of the reduced image of an interlacing pass, and the number of bytes
+    /// in each of its scanlines, excluding the filter type.
+    fn pass_layout(&self, (x0, y0, step_x, step_y): (u32, u32, u32, u32)) -> (u32, u32, usize) {
+        let width = (self.size.width + step_x - 1 - x0) / step_x;
+        let height = (self.size.height + step_y - 1 - y0) / step_y;
+        let stride = (width as usize * self.bits_per_pixel()).div_ceil(8);
+        (width, height, stride)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_RGB => 3,
+            COLOR_TYPE_GRAY_ALPHA => 2,
+            COLOR_TYPE_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// The distance between corresponding bytes of neighbouring pixels, used when filtering.
+    fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// Decode the pixel at an index in an unfiltered row.
+    fn pixel(&self, row: &[u8], index: usize) -> Result<Color, ImageError> {
+        let depth = self.bit_depth as usize;
+        let sample = |channel: usize| -> u16 {
+            let bit = (index * self.channels() + channel) * depth;
+            match depth {
+                16 => u16::from_be_bytes([row[bit / 8], row[bit / 8 + 1]]),
+                _ => ((row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8) as u16,
+            }
+        };
+        let max = ((1u32 << depth) - 1) as f32;
+        let value = |channel: usize| sample(channel) as f32 / max;
+
+        let color = match self.color_type {
+            COLOR_TYPE_PALETTE => *self
+                .palette
+                .get(sample(0) as usize)
+                .ok_or_else(|| malformed("palette index out of bounds"))?,
+            COLOR_TYPE_GRAY => Color::from([value(0); 3]),
+            COLOR_TYPE_GRAY_ALPHA => Color::from([value(0), value(0), value(0), value(1)]),
+            COLOR_TYPE_RGB => Color::from([value(0), value(1), value(2)]),
+            _ => Color::from([value(0), value(1), value(2), value(3)]),
+        };
+
+        let samples = match self.color_type {
+            COLOR_TYPE_GRAY => [sample(0); 3],
+            COLOR_TYPE_RGB => [sample(0), sample(1), sample(2)],
+            _ => return Ok(color),
+        };
+        match self.transparent {
+            Some(transparent) if transparent == samples => Ok(Color { a: 0.0, ..color }),
+            _ => Ok(color),
+        }
+    }
+}
+
+fn channel(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+pub(super) fn write<W: Write>(image: &Image, writer: &mut W) -> io::Result<()> {
+    let size = image.size();
+    writer.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&size.width.to_be_bytes());
+    header.extend_from_slice(&size.height.to_be_bytes());
+    header.extend_from_slice(&[
+        8, // bit depth
+        COLOR_TYPE_RGBA,
+        0, // compression method: deflate
+        0, // filter method: adaptive
+        0, // interlace method: none
+    ]);
+    write_chunk(writer, b"IHDR", &header)?;
+
+    // every row starts with its filter type, which is always `None`
+    let pixels = image.to_rgba8();
+    let mut scanlines = Vec::with_capacity(pixels.len() * 4 + size.height as usize);
+    for row in pixels.chunks(size.width.max(1) as usize) {
+        scanlines.push(0);
+        scanlines.extend(row.iter().flatten());
+    }
+    write_chunk(writer, b"IDAT", &zlib::compress_stored(&scanlines))?;
+
+    write_chunk(writer, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "PNG chunk is too large"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32_update(crc32_update(!0, kind), data);
+    writer.write_all(&(!crc).to_be_bytes())
+}
+
+/// Continue computing the CRC-32 of a stream of bytes. The CRC starts out as `!0` and is inverted
+/// once all bytes have been processed.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    /// A PNG containing the given header and zlib compressed scanlines.
+    fn png(header: [u8; 13], scanlines: &[u8]) -> Vec<u8> {
+        let mut data = SIGNATURE.to_vec();
+        write_chunk(&mut data, b"IHDR", &header).unwrap();
+        write_chunk(&mut data, b"IDAT", &zlib::compress_stored(scanlines)).unwrap();
+        write_chunk(&mut data, b"IEND", &[]).unwrap();
+        data
+    }
+
+    #[test]
+    fn filters_are_reversed() {
+        // 2x4 8-bit grayscale, using the sub, up, average and paeth filters
+        let header = [0, 0, 0, 2, 0, 0, 0, 4, 8, COLOR_TYPE_GRAY, 0, 0, 0];
+        let scanlines = [1, 10, 5, 2, 1, 1, 3, 10, 5, 4, 0, 0];
+        let image = read(&png(header, &scanlines)).unwrap();
+        let gray = image
+            .to_rgba8()
+            .iter()
+            .map(|pixel| pixel[0])
+            .collect::<Vec<_>>();
+        assert_eq!(gray, [10, 15, 11, 16, 15, 20, 15, 20]);
+    }
+
+    #[test]
+    fn sub_byte_palette() {
+        let header = [0, 0, 0, 3, 0, 0, 0, 1, 2, COLOR_TYPE_PALETTE, 0, 0, 0];
+        let mut data = SIGNATURE.to_vec();
+        write_chunk(&mut data, b"IHDR", &header).unwrap();
+        write_chunk(&mut data, b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]).unwrap();
+        write_chunk(&mut data, b"tRNS", &[255, 128]).unwrap();
+        write_chunk(
+            &mut data,
+            b"IDAT",
+            &zlib::compress_stored(&[0, 0b1001_0000]),
+        )
+        .unwrap();
+        write_chunk(&mut data, b"IEND", &[]).unwrap();
+
+        let image = read(&data).unwrap();
+        assert_eq!(
+            image.to_rgba8(),
+            [[0, 0, 255, 255], [0, 255, 0, 128], [255, 0, 0, 255]]
+        );
+    }
+
+    #[test]
+    fn adam7_interlacing() {
+        // 3x3 8-bit grayscale, where every pixel stores its index
+        let header = [0, 0, 0, 3, 0, 0, 0, 3, 8, COLOR_TYPE_GRAY, 0, 0, 1];
+        #[rustfmt::skip]
+        let scanlines = [
+            0, 0, // pass 1: (0, 0)
+            // pass 2 and 3 are empty
+            0, 2, // pass 4: (2, 0)
+            0, 6, 8, // pass 5: (0, 2), (2, 2)
+            0, 1, // pass 6: (1, 0)
+            0, 7, // pass 6 continued: (1, 2)
+            0, 3, 4, 5, // pass 7: row 1
+        ];
+        let image = read(&png(header, &scanlines)).unwrap();
+        let gray = image
+            .to_rgba8()
+            .iter()
+            .map(|pixel| pixel[0])
+            .collect::<Vec<_>>();
+        assert_eq!(gray, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn corrupt_checksum() {
+        let image = Image::from_pixels([1, 1].into(), vec![Color::WHITE]);
+        let mut data = Vec::new();
+        write(&image, &mut data).unwrap();
+        data[20] ^= 1;
+        assert!(matches!(read(&data), Err(ImageError::Malformed(_))));
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(!crc32_update(!0, b"IEND"), 0xae42_6082);
+        assert_eq!(!crc32_update(!0, b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn chunk_layout() {
+        let image = Image::from_pixels([1, 1].into(), vec![Color::WHITE]);
+        let mut data = Vec::new();
+        write(&image, &mut data).unwrap();
+
+        assert_eq!(&data[..8], &SIGNATURE);
+        assert_eq!(&data[8..16], b"\0\0\0\x0dIHDR");
+        assert_eq!(&data[16..24], &[0, 0, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(&data[data.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+
+        // a single filter byte followed by the pixel
+        let idat = &data[33..];
+        assert_eq!(&idat[4..8], b"IDAT");
+        let zlib = &idat[8..8 + u32::from_be_bytes([idat[0], idat[1], idat[2], idat[3]]) as usize];
+        assert_eq!(&zlib[2..7], &[1, 5, 0, 0xfa, 0xff]);
+        assert_eq!(&zlib[7..12], &[0, 255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,134 @@
+//! Netpbm pixmaps and graymaps, see <http://netpbm.sourceforge.net/doc/ppm.html> and
+//! <http://netpbm.sourceforge.net/doc/pgm.html>.
+
+use std::io::{self, Write};
+
+use super::{checked_size, malformed, unexpected_end, unsupported, Bytes, ImageError};
+use crate::{Color, Image, PixelBuffer};
+
+pub(super) fn read(data: &[u8]) -> Result<Image, ImageError> {
+    let mut bytes = Bytes::new(data);
+    if bytes.take(2).map_err(|_| unexpected_end())?[0] != b'P' {
+        return Err(malformed("missing Netpbm magic number"));
+    }
+    let (channels, binary) = match data[1] {
+        b'2' => (1, false),
+        b'3' => (3, false),
+        b'5' => (1, true),
+        b'6' => (3, true),
+        magic => return Err(unsupported(format!("Netpbm format P{}", char::from(magic)))),
+    };
+
+    let width = header_value(&mut bytes)?;
+    let height = header_value(&mut bytes)?;
+    let max_value = header_value(&mut bytes)?;
+    if max_value == 0 || max_value > u16::MAX as u32 {
+        return Err(malformed(format!("maximum value {}", max_value)));
+    }
+    let size = checked_size(width, height)?;
+
+    let sample_count = size.width as usize * size.height as usize * channels;
+    let mut samples = Vec::with_capacity(sample_count);
+    if binary {
+        // exactly one whitespace character separates the header from the raster
+        if !bytes.u8()?.is_ascii_whitespace() {
+            return Err(malformed("expected whitespace after the header"));
+        }
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        let raster = bytes.take(sample_count * sample_size)?;
+        if sample_size == 1 {
+            samples.extend(raster.iter().map(|&sample| sample as u32));
+        } else {
+            samples.extend(
+                raster
+                    .chunks_exact(2)
+                    .map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as u32),
+            );
+        }
+    } else {
+        for _ in 0..sample_count {
+            samples.push(header_value(&mut bytes)?);
+        }
+    }
+
+    let scale = 1.0 / max_value as f32;
+    let channel = |sample: u32| sample.min(max_value) as f32 * scale;
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|pixel| match *pixel {
+            [gray] => Color::from([channel(gray); 3]),
+            [r, g, b] => Color::from([channel(r), channel(g), channel(b)]),
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(Image::from_pixels(size, pixels))
+}
+
+/// Read a decimal number, skipping any whitespace and comments before it.
+fn header_value(bytes: &mut Bytes) -> Result<u32, ImageError> {
+    loop {
+        match bytes.peek() {
+            Some(b'#') => while !matches!(bytes.u8()?, b'\n' | b'\r') {},
+            Some(byte) if byte.is_ascii_whitespace() => bytes.skip(1)?,
+            Some(_) => break,
+            None => return Err(unexpected_end()),
+        }
+    }
+
+    let mut value = 0u32;
+    let mut digits = 0;
+    while let Some(digit @ b'0'..=b'9') = bytes.peek() {
+        value = value
+            .checked_mul(10)
+            .and_then(|value| value.checked_add((digit - b'0') as u32))
+            .ok_or_else(|| malformed("number too large"))?;
+        digits += 1;
+        bytes.skip(1)?;
+    }
+
+    match digits {
+        0 => Err(malformed("expected a number")),
+        _ => Ok(value),
+    }
+}
+
+pub(super) fn write<W: Write>(image: &Image, writer: &mut W) -> io::Result<()> {
+    let size = image.size();
+    write!(writer, "P6\n{} {}\n255\n", size.width, size.height)?;
+
+    let data = image
+        .to_rgba8()
+        .iter()
+        .flat_map(|&[r, g, b, _]| [r, g, b])
+        .collect::<Vec<u8>>();
+    writer.write_all(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn ascii_graymap_with_comments() {
+        let data = b"P2\n# a comment\n2 1 # another\n4\n0 2\n";
+        let image = read(data).unwrap();
+        assert_eq!(image.size(), [2, 1].into());
+        assert_eq!(image.pixels(), [Color::BLACK, Color::from([0.5; 3])]);
+    }
+
+    #[test]
+    fn sixteen_bit_samples() {
+        let data = b"P6 1 1 65535 \xff\xff\x00\x00\x80\x00";
+        let image = read(data).unwrap();
+        assert_eq!(image.to_rgba8(), [[255, 0, 128, 255]]);
+    }
+
+    #[test]
+    fn header_and_pixels() {
+        let image = Image::from_pixels([2, 1].into(), vec![Color::WHITE, Color::TRANSPARENT]);
+        let mut data = Vec::new();
+        write(&image, &mut data).unwrap();
+        assert_eq!(data, b"P6\n2 1\n255\n\xff\xff\xff\x00\x00\x00");
+    }
+}
@@ -0,0 +1,229 @@
+//! Truevision TGA images. True-color, color-mapped and grayscale images are read, either
+//! uncompressed or run-length encoded, and images are written as uncompressed true-color.
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use super::{checked_size, malformed, unsupported, Bytes, ImageError};
+use crate::{Color, Image, PixelBuffer};
+
+/// Image type of uncompressed true-color images.
+const UNCOMPRESSED_TRUE_COLOR: u8 = 2;
+
+/// Image type flag for run-length encoded images.
+const RUN_LENGTH_ENCODED: u8 = 8;
+
+/// Image descriptor flag for images stored from the right column to the left.
+const RIGHT_TO_LEFT: u8 = 1 << 4;
+
+/// Image descriptor flag for images stored from the top row down.
+const TOP_TO_BOTTOM: u8 = 1 << 5;
+
+pub(super) fn read(data: &[u8]) -> Result<Image, ImageError> {
+    let mut bytes = Bytes::new(data);
+    let id_length = bytes.u8()?;
+    let has_color_map = bytes.u8()?;
+    let image_type = bytes.u8()?;
+    let color_map_first = bytes.u16_le()?;
+    let color_map_length = bytes.u16_le()?;
+    let color_map_depth = bytes.u8()?;
+    bytes.skip(4)?;
+    let width = bytes.u16_le()?;
+    let height = bytes.u16_le()?;
+    let depth = bytes.u8()?;
+    let descriptor = bytes.u8()?;
+
+    let alpha_bits = descriptor & 0x0f;
+    let size = checked_size(width as u32, height as u32)?;
+    bytes.skip(id_length as usize)?;
+
+    let color_map = match has_color_map {
+        0 => Vec::new(),
+        1 => {
+            let entry_size = (color_map_depth as usize).div_ceil(8);
+            bytes
+                .take(color_map_length as usize * entry_size)?
+                .chunks_exact(entry_size)
+                .map(|entry| true_color(entry, alpha_bits))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        _ => return Err(malformed("invalid color map type")),
+    };
+
+    let kind = image_type & !RUN_LENGTH_ENCODED;
+    if !(1..=3).contains(&kind) {
+        return Err(unsupported(format!("TGA image type {}", image_type)));
+    }
+    let decode = |pixel: &[u8]| match kind {
+        1 => {
+            let index = match *pixel {
+                [index] => index as usize,
+                [lo, hi] => u16::from_le_bytes([lo, hi]) as usize,
+                _ => return Err(unsupported(format!("{}-bit color map index", depth))),
+            };
+            index
+                .checked_sub(color_map_first as usize)
+                .and_then(|index| color_map.get(index).copied())
+                .ok_or_else(|| malformed("color map index out of bounds"))
+        }
+        2 => true_color(pixel, alpha_bits),
+        _ => match *pixel {
+            [gray] => Ok(Color::from([channel(gray); 3])),
+            [gray, alpha] => Ok(Color::from([
+                channel(gray),
+                channel(gray),
+                channel(gray),
+                channel(alpha),
+            ])),
+            _ => Err(unsupported(format!("{}-bit grayscale", depth))),
+        },
+    };
+
+    let pixel_size = (depth as usize).div_ceil(8);
+    if pixel_size == 0 {
+        return Err(malformed("pixels have no bits"));
+    }
+    let pixel_count = size.width as usize * size.height as usize;
+    let mut pixels = Vec::with_capacity(pixel_count);
+    if image_type & RUN_LENGTH_ENCODED == 0 {
+        for pixel in bytes
+            .take(pixel_count * pixel_size)?
+            .chunks_exact(pixel_size)
+        {
+            pixels.push(decode(pixel)?);
+        }
+    } else {
+        while pixels.len() < pixel_count {
+            let packet = bytes.u8()?;
+            let count = (packet & 0x7f) as usize + 1;
+            if packet & 0x80 != 0 {
+                let color = decode(bytes.take(pixel_size)?)?;
+                pixels.extend(std::iter::repeat_n(color, count));
+            } else {
+                for pixel in bytes.take(count * pixel_size)?.chunks_exact(pixel_size) {
+                    pixels.push(decode(pixel)?);
+                }
+            }
+        }
+        pixels.truncate(pixel_count);
+    }
+
+    let width = size.width as usize;
+    if descriptor & RIGHT_TO_LEFT != 0 {
+        pixels.chunks_exact_mut(width).for_each(|row| row.reverse());
+    }
+    if descriptor & TOP_TO_BOTTOM == 0 {
+        pixels = pixels
+            .chunks_exact(width)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+    }
+
+    Ok(Image::from_pixels(size, pixels))
+}
+
+fn true_color(pixel: &[u8], alpha_bits: u8) -> Result<Color, ImageError> {
+    match *pixel {
+        [lo, hi] => {
+            let value = u16::from_le_bytes([lo, hi]);
+            let five_bits = |shift: u16| ((value >> shift) & 0x1f) as f32 / 31.0;
+            let alpha = if alpha_bits == 0 || value & 0x8000 != 0 {
+                1.0
+            } else {
+                0.0
+            };
+            Ok(Color::from([
+                five_bits(10),
+                five_bits(5),
+                five_bits(0),
+                alpha,
+            ]))
+        }
+        [b, g, r] => Ok(Color::from([channel(r), channel(g), channel(b)])),
+        [b, g, r, a] => {
+            let alpha = if alpha_bits == 0 { 1.0 } else { channel(a) };
+            Ok(Color::from([channel(r), channel(g), channel(b), alpha]))
+        }
+        _ => Err(unsupported(format!("{}-bit true-color", pixel.len() * 8))),
+    }
+}
+
+fn channel(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+pub(super) fn write<W: Write>(image: &Image, writer: &mut W) -> io::Result<()> {
+    let size = image.size();
+    let dimension = |value: u32| {
+        u16::try_from(value).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image is too large for the TGA format",
+            )
+        })
+    };
+    let [width_lo, width_hi] = dimension(size.width)?.to_le_bytes();
+    let [height_lo, height_hi] = dimension(size.height)?.to_le_bytes();
+
+    #[rustfmt::skip]
+    let header = [
+        0, // no image id
+        0, // no color map
+        UNCOMPRESSED_TRUE_COLOR,
+        0, 0, 0, 0, 0, // color map specification
+        0, 0, 0, 0, // origin
+        width_lo, width_hi,
+        height_lo, height_hi,
+        32, // bits per pixel
+        TOP_TO_BOTTOM | 8, // 8 bits of alpha
+    ];
+    writer.write_all(&header)?;
+
+    let data = image
+        .to_rgba8()
+        .iter()
+        .flat_map(|&[r, g, b, a]| [b, g, r, a])
+        .collect::<Vec<u8>>();
+    writer.write_all(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn run_length_encoded_bottom_to_top() {
+        #[rustfmt::skip]
+        let data = [
+            0, 0, UNCOMPRESSED_TRUE_COLOR | RUN_LENGTH_ENCODED,
+            0, 0, 0, 0, 0,
+            0, 0, 0, 0,
+            3, 0, 2, 0,
+            24, 0,
+            // a run of three red pixels, then three raw pixels
+            0x82, 0, 0, 255,
+            0x02, 0, 255, 0, 255, 0, 0, 0, 0, 0,
+        ];
+        let image = read(&data).unwrap();
+
+        let red = Color::from([1.0, 0.0, 0.0]);
+        let green = Color::from([0.0, 1.0, 0.0]);
+        let blue = Color::from([0.0, 0.0, 1.0]);
+        assert_eq!(image.pixels(), [green, blue, Color::BLACK, red, red, red]);
+    }
+
+    #[test]
+    fn header_and_pixels() {
+        let image = Image::from_pixels([1, 2].into(), vec![Color::from([1.0, 0.0, 0.0]); 2]);
+        let mut data = Vec::new();
+        write(&image, &mut data).unwrap();
+
+        assert_eq!(data.len(), 18 + 2 * 4);
+        assert_eq!(data[2], UNCOMPRESSED_TRUE_COLOR);
+        assert_eq!(&data[12..18], &[1, 0, 2, 0, 32, 0x28]);
+        assert_eq!(&data[18..22], &[0, 0, 255, 255]);
+    }
+}
@@ -0,0 +1,452 @@
+//! Compression and decompression of zlib streams, see RFC 1950 (zlib) and RFC 1951 (deflate).
+
+use super::ImageError;
+
+/// Largest number of bytes in an uncompressed deflate block.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// Largest number of bits in a Huffman code.
+const MAX_BITS: usize = 15;
+
+/// Base lengths of the length codes 257..=285, and the number of extra bits following them.
+const LENGTHS: [(u16, u8); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+/// Base distances of the distance codes 0..=29, and the number of extra bits following them.
+const DISTANCES: [(u16, u8); 30] = [
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 1),
+    (7, 1),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 4),
+    (49, 4),
+    (65, 5),
+    (97, 5),
+    (129, 6),
+    (193, 6),
+    (257, 7),
+    (385, 7),
+    (513, 8),
+    (769, 8),
+    (1025, 9),
+    (1537, 9),
+    (2049, 10),
+    (3073, 10),
+    (4097, 11),
+    (6145, 11),
+    (8193, 12),
+    (12289, 12),
+    (16385, 13),
+    (24577, 13),
+];
+
+/// Order in which the code lengths of the code length alphabet are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompress a zlib stream, which is malformed if it decompresses to more than `max_length`
+/// bytes.
+pub(super) fn decompress(data: &[u8], max_length: usize) -> Result<Vec<u8>, ImageError> {
+    if data.len() < 6 {
+        return Err(malformed("zlib stream is too short"));
+    }
+
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+        return Err(ImageError::Unsupported(
+            "zlib compression method other than deflate".into(),
+        ));
+    }
+    if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return Err(malformed("invalid zlib header checksum"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(ImageError::Unsupported("zlib preset dictionary".into()));
+    }
+
+    let mut bits = BitReader::new(&data[2..]);
+    let output = inflate(&mut bits, max_length)?;
+
+    let trailer = bits.align_to_byte();
+    let checksum = trailer
+        .get(..4)
+        .ok_or_else(|| malformed("missing zlib checksum"))?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&output)
+    {
+        return Err(malformed("zlib checksum mismatch"));
+    }
+
+    Ok(output)
+}
+
+/// Wrap the data in a zlib stream of uncompressed deflate blocks.
+pub(super) fn compress_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_STORED_BLOCK + 1;
+    let mut output = Vec::with_capacity(data.len() + 5 * blocks + 6);
+
+    // deflate with a 32K window, and no preset dictionary
+    output.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        output.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        output.push(last as u8);
+        output.extend_from_slice(&len.to_le_bytes());
+        output.extend_from_slice(&(!len).to_le_bytes());
+        output.extend_from_slice(chunk);
+    }
+
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+/// Decompress a raw deflate stream.
+fn inflate(bits: &mut BitReader, max_length: usize) -> Result<Vec<u8>, ImageError> {
+    let mut output = Vec::new();
+
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                let data = bits.align_to_byte();
+                if data.len() < 4 {
+                    return Err(unexpected_end());
+                }
+                let len = u16::from_le_bytes([data[0], data[1]]);
+                let inv_len = u16::from_le_bytes([data[2], data[3]]);
+                if len != !inv_len {
+                    return Err(malformed("invalid stored block length"));
+                }
+                let block = data.get(4..4 + len as usize).ok_or_else(unexpected_end)?;
+                if output.len() + block.len() > max_length {
+                    return Err(too_long());
+                }
+                output.extend_from_slice(block);
+                bits.skip_bytes(4 + len as usize);
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(bits, &mut output, max_length, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                inflate_block(bits, &mut output, max_length, &literals, &distances)?;
+            }
+            _ => return Err(malformed("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    max_length: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), ImageError> {
+    loop {
+        let symbol = literals.decode(bits)?;
+        match symbol {
+            0..=255 if output.len() == max_length => return Err(too_long()),
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let (base, extra) = LENGTHS[symbol as usize - 257];
+                let length = base as usize + bits.read(extra)? as usize;
+
+                let symbol = distances.decode(bits)?;
+                let (base, extra) = *DISTANCES
+                    .get(symbol as usize)
+                    .ok_or_else(|| malformed("invalid deflate distance code"))?;
+                let distance = base as usize + bits.read(extra)? as usize;
+                if distance > output.len() {
+                    return Err(malformed("deflate distance too far back"));
+                }
+                if output.len() + length > max_length {
+                    return Err(too_long());
+                }
+
+                // the copied range may overlap the bytes being written
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+            _ => return Err(malformed("invalid deflate length code")),
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let literals = Huffman::new(&lengths).expect("fixed literal codes are valid");
+    let distances = Huffman::new(&[5; 30]).expect("fixed distance codes are valid");
+    (literals, distances)
+}
+
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), ImageError> {
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.read(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(bits)? {
+            length @ 0..=15 => (length as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| malformed("repeated code length without a previous length"))?;
+                (previous, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(malformed("too many deflate code lengths"));
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Canonical Huffman codes, decoded one bit at a time.
+struct Huffman {
+    /// Number of codes of every length.
+    counts: [u16; MAX_BITS + 1],
+    /// The symbols, ordered by their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Create the codes given the length of the code of every symbol, where a length of `0`
+    /// means that the symbol is unused.
+    fn new(lengths: &[u8]) -> Result<Huffman, ImageError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // make sure that the code is not over-subscribed
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                return Err(malformed("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, ImageError> {
+        // the first code of the current length, and the index of its symbol
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(malformed("invalid Huffman code"))
+    }
+}
+
+/// Reads bits starting with the least significant bit of every byte.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0,
+            bit: 0,
+        }
+    }
+
+    fn read(&mut self, count: u8) -> Result<u32, ImageError> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.position).ok_or_else(unexpected_end)?;
+            value |= u32::from((byte >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skip the remaining bits of the current byte, and get the remaining bytes.
+    fn align_to_byte(&mut self) -> &'a [u8] {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+        self.data.get(self.position..).unwrap_or(&[])
+    }
+
+    fn skip_bytes(&mut self, count: usize) {
+        self.position += count;
+    }
+}
+
+fn malformed(message: &str) -> ImageError {
+    ImageError::Malformed(message.into())
+}
+
+fn unexpected_end() -> ImageError {
+    malformed("unexpected end of compressed data")
+}
+
+fn too_long() -> ImageError {
+    malformed("decompressed data is longer than expected")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_blocks_round_trip() {
+        let data = (0..200_000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        let compressed = compress_stored(&data);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+    }
+
+    #[test]
+    fn adler32_checksum() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn fixed_and_dynamic_huffman_blocks() {
+        // `zlib.compress(b"hello hello hello hello")`, which uses fixed codes
+        let fixed = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        assert_eq!(decompress(&fixed, 23).unwrap(), b"hello hello hello hello");
+        // the repeated words are copied from earlier output
+        assert!(decompress(&fixed, 22).is_err());
+        assert!(decompress(&fixed, 5).is_err());
+
+        // `zlib.compress(data, 9)`, which uses dynamic codes
+        let dynamic = [
+            0x78, 0xda, 0x3d, 0x8b, 0xc1, 0x09, 0x00, 0x30, 0x0c, 0x02, 0x67, 0xf5, 0x82, 0xfb,
+            0xaf, 0xd0, 0x28, 0x34, 0x3e, 0x04, 0xf5, 0x14, 0xd8, 0x9e, 0x2f, 0x83, 0x24, 0x68,
+            0xdb, 0x7e, 0x3d, 0x51, 0x11, 0x47, 0x76, 0xa5, 0xa4, 0xef, 0x1f, 0xf2, 0x01, 0xe5,
+            0xe8, 0x1e, 0xfa,
+        ];
+        let data = (0..120u32)
+            .flat_map(|i| std::iter::repeat_n(b'a' + (i * i % 7) as u8, (i % 5 + 1) as usize))
+            .take(80)
+            .collect::<Vec<_>>();
+        assert_eq!(decompress(&dynamic, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn corrupt_streams_are_errors() {
+        assert!(decompress(&[0x78, 0x9c, 0xff, 0xff, 0xff, 0xff], 100).is_err());
+        assert!(decompress(&[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x02], 100).is_err());
+    }
+}
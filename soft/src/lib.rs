@@ -1,18 +1,42 @@
 #[macro_use]
 mod macros;
+pub mod blend;
+mod clip;
+pub mod image;
 pub mod matrix;
+pub mod mesh;
+pub mod multisample;
+pub mod parallel;
+mod raster;
+pub mod stencil;
+pub mod target;
+#[cfg(test)]
+mod test_util;
+pub mod texture;
+pub mod topology;
 
-pub use soft_macros::Interpolate;
+pub use soft_macros::{FragmentOutput, Interpolate};
 
-use std::ops::{Add, Mul, Sub};
+use std::ops::{Add, Mul, Range, Sub};
 
+pub use crate::blend::*;
+pub use crate::image::*;
 pub use crate::matrix::*;
-
-#[derive(Debug, Copy, Clone)]
+pub use crate::mesh::*;
+pub use crate::multisample::*;
+pub use crate::parallel::*;
+pub use crate::stencil::*;
+pub use crate::target::*;
+pub use crate::texture::*;
+pub use crate::topology::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
     pub b: f32,
+    /// Opacity of the color, where `0.0` is fully transparent and `1.0` fully opaque.
+    pub a: f32,
 }
 
 impl Color {
@@ -20,13 +44,27 @@ impl Color {
         r: 0.0,
         g: 0.0,
         b: 0.0,
+        a: 1.0,
     };
 
     pub const WHITE: Color = Color {
         r: 1.0,
         g: 1.0,
         b: 1.0,
+        a: 1.0,
+    };
+
+    pub const TRANSPARENT: Color = Color {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 0.0,
     };
+
+    #[inline(always)]
+    pub fn to_arr(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
 }
 
 impl Default for Color {
@@ -37,7 +75,13 @@ impl Default for Color {
 
 impl From<[f32; 3]> for Color {
     fn from([r, g, b]: [f32; 3]) -> Self {
-        Color { r, g, b }
+        Color { r, g, b, a: 1.0 }
+    }
+}
+
+impl From<[f32; 4]> for Color {
+    fn from([r, g, b, a]: [f32; 4]) -> Self {
+        Color { r, g, b, a }
     }
 }
 
@@ -47,11 +91,23 @@ impl From<Vector3> for Color {
             r: vec.x,
             g: vec.y,
             b: vec.z,
+            a: 1.0,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl From<Vector4> for Color {
+    fn from(vec: Vector4) -> Self {
+        Color {
+            r: vec.x,
+            g: vec.y,
+            b: vec.z,
+            a: vec.w,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Triangle<T> {
     pub vertices: [T; 3],
 }
@@ -62,13 +118,24 @@ impl<T> From<[T; 3]> for Triangle<T> {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Line<T> {
+    pub vertices: [T; 2],
+}
+
+impl<T> From<[T; 2]> for Line<T> {
+    fn from(vertices: [T; 2]) -> Self {
+        Line { vertices }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct PixelCoord {
     pub x: u32,
     pub y: u32,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Dimensions {
     pub width: u32,
     pub height: u32,
@@ -80,16 +147,64 @@ impl From<[u32; 2]> for Dimensions {
     }
 }
 
+/// A rectangle of pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The region of the framebuffer that normalized device coordinates are mapped to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    /// Left edge of the viewport, in pixels.
+    pub x: f32,
+    /// Top edge of the viewport, in pixels.
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// The depth that the near plane is mapped to.
+    pub min_depth: f32,
+    /// The depth that the far plane is mapped to.
+    pub max_depth: f32,
+}
+
+/// Stores the depth of the closest fragment drawn to each pixel, and optionally a stencil value
+/// for each pixel.
 #[derive(Clone)]
-pub struct Texture<T> {
+pub struct DepthBuffer {
     size: Dimensions,
-    pixels: Vec<T>,
+    depths: Vec<f32>,
+    stencil: Option<Vec<u8>>,
+}
+
+/// The comparison used to determine if a fragment passes the depth or stencil test. The value of
+/// the fragment is on the left-hand side of the comparison and the stored value on the
+/// right-hand side.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CompareFunction {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
 }
 
+/// The comparison used by the depth test.
+pub type DepthCompare = CompareFunction;
+
 pub trait PixelBuffer {
     /// Get the size of the buffer
     fn size(&self) -> Dimensions;
 
+    /// Get the color of a pixel
+    fn get(&self, pixel: PixelCoord) -> Color;
+
     /// Set a pixel to a specific color
     fn set(&mut self, pixel: PixelCoord, color: Color);
 }
@@ -106,159 +221,436 @@ pub enum WindingOrder {
 
 pub trait ShaderModule {
     type VertexInput;
-    type FragmentInput: Interpolate;
+    /// The output of the vertex shader. It is cloned when a vertex is shared by multiple
+    /// primitives, since the vertex shader only runs once per vertex in indexed draw calls.
+    type FragmentInput: Interpolate + Clone;
+    /// The colors written by the fragment shader, usually a single [`Color`]. Outputs with
+    /// multiple colors are written to the attachments of the [`RenderTarget`] in order.
+    /// Shaders that discard fragments return an `Option` of the output instead, where `None`
+    /// discards the fragment.
+    type Output: FragmentOutput;
 
     const FRONT_FACE: Option<WindingOrder> = None;
 
+    /// Comparison used to test fragments against the depth buffer, if one is bound.
+    const DEPTH_COMPARE: CompareFunction = CompareFunction::Less;
+
+    /// Write the depth of fragments that pass the depth test to the depth buffer.
+    const DEPTH_WRITE: bool = true;
+
     fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput);
-    fn fragment_shader(&self, fragment: &Self::FragmentInput) -> Color;
+    fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> Self::Output;
+}
+
+/// A fragment about to be shaded.
+///
+/// Like on a GPU, the derivatives of the fragment's inputs are the differences to a neighbouring
+/// pixel in the 2x2 quad of pixels the fragment belongs to. The inputs of the neighbouring pixel
+/// are interpolated even if it is not covered by the primitive, and only when the derivative is
+/// requested. The inputs of points are constant, so their derivatives are zero, including for
+/// the vertices of triangles drawn in [`PolygonMode::Point`].
+pub struct Fragment<'a, F> {
+    input: &'a F,
+    primitive: &'a raster::Primitive<F>,
+    pixel: PixelCoord,
+    depth: f32,
+}
+
+impl<'a, F: Interpolate> Fragment<'a, F> {
+    /// The interpolated output of the vertex shader.
+    pub fn input(&self) -> &'a F {
+        self.input
+    }
+
+    /// The pixel covered by the fragment.
+    pub fn pixel(&self) -> PixelCoord {
+        self.pixel
+    }
+
+    /// The depth of the fragment, in the range `[0, 1]`.
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+
+    /// The change in an attribute of the input when moving one pixel to the right.
+    pub fn dfdx<T: Sub<T, Output = T>>(&self, attribute: impl Fn(&F) -> T) -> T {
+        if self.primitive.is_point() {
+            return attribute(self.input) - attribute(self.input);
+        }
+        let neighbour = self
+            .primitive
+            .interpolate_at(self.pixel.x ^ 1, self.pixel.y);
+        if self.pixel.x & 1 == 0 {
+            attribute(&neighbour) - attribute(self.input)
+        } else {
+            attribute(self.input) - attribute(&neighbour)
+        }
+    }
+
+    /// The change in an attribute of the input when moving one pixel down.
+    pub fn dfdy<T: Sub<T, Output = T>>(&self, attribute: impl Fn(&F) -> T) -> T {
+        if self.primitive.is_point() {
+            return attribute(self.input) - attribute(self.input);
+        }
+        let neighbour = self
+            .primitive
+            .interpolate_at(self.pixel.x, self.pixel.y ^ 1);
+        if self.pixel.y & 1 == 0 {
+            attribute(&neighbour) - attribute(self.input)
+        } else {
+            attribute(self.input) - attribute(&neighbour)
+        }
+    }
 }
 
 pub trait VertexBuffer<V> {
     fn get_vertex(&self, index: u32) -> V;
 }
 
-type VertexIndex = u32;
+pub type VertexIndex = u32;
 
-pub fn draw<P: PixelBuffer, S: ShaderModule, V: VertexBuffer<S::VertexInput>>(
-    pixels: &mut P,
-    shaders: &S,
-    vertex_buffer: &V,
-    indices: &[Triangle<VertexIndex>],
-) {
-    let mut triangles = Vec::with_capacity(indices.len());
-    for triangle in indices {
-        let vertex_0 = vertex_buffer.get_vertex(triangle.vertices[0]);
-        let vertex_1 = vertex_buffer.get_vertex(triangle.vertices[1]);
-        let vertex_2 = vertex_buffer.get_vertex(triangle.vertices[2]);
-
-        let (pos_0, data_0) = shaders.vertex_shader(&vertex_0);
-        let (pos_1, data_1) = shaders.vertex_shader(&vertex_1);
-        let (pos_2, data_2) = shaders.vertex_shader(&vertex_2);
-
-        let unproject = |vector: Vector4| {
-            let inv_w = 1.0 / vector.w;
-            Vector4 {
-                x: vector.x * inv_w,
-                y: vector.y * inv_w,
-                z: vector.z * inv_w,
-                w: inv_w,
-            }
-        };
+/// How the triangles of a draw call are rasterized, see [`Rasterizer::polygon_mode`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PolygonMode {
+    /// Cover the interior of triangles.
+    Fill,
+    /// Draw the edges of triangles as lines.
+    Line,
+    /// Draw the vertices of triangles as points of [`Rasterizer::point_size`].
+    Point,
+}
 
-        let positions = [unproject(pos_0), unproject(pos_1), unproject(pos_2)];
-        let vertex_datas = [data_0, data_1, data_2];
+/// Fixed-function state used when rasterizing primitives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rasterizer<E = SingleThreaded> {
+    /// The threads the tiles of the framebuffer are rasterized on, either [`SingleThreaded`] or
+    /// [`MultiThreaded`]. The output is the same regardless of the number of threads.
+    pub threads: E,
+    /// How fragments are blended with the colors already in the pixel buffer. If `None` the
+    /// fragments overwrite the existing colors.
+    pub blend: Option<BlendState>,
+    /// The channels of the pixel buffer that fragments are written to, after blending. With
+    /// [`ColorWriteMask::NONE`] the pixel buffer is never written to, but the fragment shader still
+    /// runs in case it discards fragments. Use [`draw_depth`](Rasterizer::draw_depth) to skip it
+    /// entirely.
+    pub color_write: ColorWriteMask,
+    /// Width and height (in pixels) of the squares drawn by
+    /// [`draw_points`](Rasterizer::draw_points) and in [`PolygonMode::Point`].
+    pub point_size: f32,
+    /// Draw triangles filled, or only their edges or vertices. Back faces are culled and
+    /// triangles are clipped before they are outlined, and edges introduced by splitting clipped
+    /// triangles are left out.
+    pub polygon_mode: PolygonMode,
+    /// The region of the framebuffer primitives are drawn to. If `None` the viewport covers the
+    /// entire framebuffer.
+    pub viewport: Option<Viewport>,
+    /// Pixels outside the scissor rectangle are never drawn to.
+    pub scissor: Option<Rect>,
+    /// Test and update the stencil values in the depth buffer. Ignored if the depth buffer has no
+    /// stencil attachment, and when drawing to a [`MultisampleBuffer`].
+    pub stencil: Option<StencilState>,
+}
+
+/// Counters collected during a draw call.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DrawStatistics {
+    /// Number of times the vertex shader was run, once for every unique vertex index. Draws
+    /// without an index buffer run it every time a vertex is used.
+    pub vertex_shader_invocations: usize,
+    /// Number of vertices that reused the output of an earlier invocation of the vertex shader.
+    pub vertex_cache_hits: usize,
+}
 
-        triangles.push((positions, vertex_datas));
+impl Default for Rasterizer {
+    fn default() -> Self {
+        Rasterizer {
+            threads: SingleThreaded,
+            blend: None,
+            color_write: ColorWriteMask::ALL,
+            point_size: 1.0,
+            polygon_mode: PolygonMode::Fill,
+            viewport: None,
+            scissor: None,
+            stencil: None,
+        }
     }
+}
 
-    let size = pixels.size();
-    for (vertices, vertex_data) in triangles {
-        let corners = [
-            Vector2::from(vertices[0]),
-            Vector2::from(vertices[1]),
-            Vector2::from(vertices[2]),
-        ];
+impl Rasterizer<MultiThreaded> {
+    /// Rasterize on as many threads as there are available cores.
+    pub fn parallel() -> Rasterizer<MultiThreaded> {
+        Rasterizer::default().with_threads(MultiThreaded::available())
+    }
+}
 
-        // cull back faces
-        if let Some(front_order) = S::FRONT_FACE {
-            if triangle_winding_order(corners) != front_order {
-                continue;
-            }
+impl<E> Rasterizer<E> {
+    /// Use the same state, but rasterize on other threads.
+    pub fn with_threads<T>(self, threads: T) -> Rasterizer<T> {
+        Rasterizer {
+            threads,
+            blend: self.blend,
+            color_write: self.color_write,
+            point_size: self.point_size,
+            polygon_mode: self.polygon_mode,
+            viewport: self.viewport,
+            scissor: self.scissor,
+            stencil: self.stencil,
         }
+    }
+
+    pub fn draw<P, S, V>(
+        &self,
+        pixels: &mut P,
+        depth_buffer: Option<&mut DepthBuffer>,
+        shaders: &S,
+        vertex_buffer: &V,
+        indices: &[Triangle<VertexIndex>],
+    ) -> DrawStatistics
+    where
+        P: RenderTarget,
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        let size = pixels.target_size();
+        let (triangles, statistics) =
+            raster::shade_triangles(shaders, vertex_buffer, indices, size, self, true, true);
+        self.rasterize(pixels, depth_buffer, shaders, &triangles);
+        statistics
+    }
+
+    /// Draw triangles assembled from a sequence of vertex indices.
+    pub fn draw_indexed<P, S, V>(
+        &self,
+        pixels: &mut P,
+        depth_buffer: Option<&mut DepthBuffer>,
+        shaders: &S,
+        vertex_buffer: &V,
+        topology: Topology,
+        indices: &[VertexIndex],
+    ) -> DrawStatistics
+    where
+        P: RenderTarget,
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        let triangles = topology.triangles(indices);
+        self.draw(pixels, depth_buffer, shaders, vertex_buffer, &triangles)
+    }
+
+    /// Draw triangles assembled from a range of consecutive vertices, without an index buffer.
+    /// Vertices are shaded once for every triangle that uses them.
+    pub fn draw_arrays<P, S, V>(
+        &self,
+        pixels: &mut P,
+        depth_buffer: Option<&mut DepthBuffer>,
+        shaders: &S,
+        vertex_buffer: &V,
+        topology: Topology,
+        vertices: Range<VertexIndex>,
+    ) -> DrawStatistics
+    where
+        P: RenderTarget,
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        let indices = vertices.collect::<Vec<_>>();
+        let triangles = topology.triangles(&indices);
+
+        // vertices of an array are only shared by a few neighbouring triangles, so they are shaded
+        // every time they are used instead of being cached
+        let size = pixels.target_size();
+        let (triangles, statistics) =
+            raster::shade_triangles(shaders, vertex_buffer, &triangles, size, self, false, true);
+        self.rasterize(pixels, depth_buffer, shaders, &triangles);
+        statistics
+    }
+
+    /// Draw lines one pixel wide. The outputs of the vertex shader are interpolated along the
+    /// line.
+    pub fn draw_lines<P, S, V>(
+        &self,
+        pixels: &mut P,
+        depth_buffer: Option<&mut DepthBuffer>,
+        shaders: &S,
+        vertex_buffer: &V,
+        indices: &[Line<VertexIndex>],
+    ) -> DrawStatistics
+    where
+        P: RenderTarget,
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        let (lines, statistics) =
+            raster::shade_lines(shaders, vertex_buffer, indices, pixels.target_size(), self);
+        self.rasterize(pixels, depth_buffer, shaders, &lines);
+        statistics
+    }
 
-        // cull triangles that are obviously outside clip space
-        let (min, max) = triangle_bounds(&vertices);
-        if max.x < -1.0 || 1.0 < min.x || max.y < -1.0 || 1.0 < min.y || max.z < 0.0 || 1.0 < min.z
-        {
-            continue;
+    /// Draw every vertex as a square [`point_size`](Rasterizer::point_size) pixels wide. Points
+    /// whose center is outside the visible volume are not drawn.
+    pub fn draw_points<P, S, V>(
+        &self,
+        pixels: &mut P,
+        depth_buffer: Option<&mut DepthBuffer>,
+        shaders: &S,
+        vertex_buffer: &V,
+        indices: &[VertexIndex],
+    ) -> DrawStatistics
+    where
+        P: RenderTarget,
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        let size = pixels.target_size();
+        let (points, statistics) =
+            raster::shade_points(shaders, vertex_buffer, indices, size, self);
+        self.rasterize(pixels, depth_buffer, shaders, &points);
+        statistics
+    }
+
+    /// Draw only the depth of triangles, without running the fragment shader, such as when
+    /// rendering a shadow map or a depth pre-pass. The outputs of the vertex shader are never
+    /// interpolated, which makes this cheaper than drawing with [`ColorWriteMask::NONE`]. Shaders
+    /// whose [`Output`](ShaderModule::Output) can discard fragments or replace their depth still
+    /// run the fragment shader, so that alpha tested geometry casts the right shadows. Convert the
+    /// depth buffer into a [`Texture<f32>`](Texture) to sample it, for example with
+    /// [`sample_compare`](Texture::sample_compare).
+    pub fn draw_depth<S, V>(
+        &self,
+        depth_buffer: &mut DepthBuffer,
+        shaders: &S,
+        vertex_buffer: &V,
+        indices: &[Triangle<VertexIndex>],
+    ) -> DrawStatistics
+    where
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        let size = depth_buffer.size();
+        let interpolate =
+            <S::Output as FragmentOutput>::DISCARDS || <S::Output as FragmentOutput>::WRITES_DEPTH;
+        let (triangles, statistics) = raster::shade_triangles(
+            shaders,
+            vertex_buffer,
+            indices,
+            size,
+            self,
+            true,
+            interpolate,
+        );
+
+        let mut tiles = raster::bin_primitives(size, &triangles);
+        for tile in &mut tiles {
+            tile.load_depth(Some(depth_buffer), self);
         }
 
-        let pixel_x_min = ((0.5 + 0.5 * min.x).max(0.0) * size.width as f32).floor() as u32;
-        let pixel_x_max = ((0.5 + 0.5 * max.x).min(1.0) * size.width as f32).ceil() as u32;
-        let pixel_y_min = ((0.5 - 0.5 * max.y).max(0.0) * size.height as f32).floor() as u32;
-        let pixel_y_max = ((0.5 - 0.5 * min.y).min(1.0) * size.height as f32).ceil() as u32;
-
-        for y in pixel_y_min..pixel_y_max {
-            for x in pixel_x_min..pixel_x_max {
-                let pixel = PixelCoord { x, y };
-
-                let frag_coord = Vector2 {
-                    x: 2.0 * (0.5 + pixel.x as f32) / size.width as f32 - 1.0,
-                    y: 1.0 - 2.0 * (0.5 + pixel.y as f32) / size.height as f32,
-                };
-
-                if let Some(barycentric) = barycentric_coords(corners, frag_coord) {
-                    let depth =
-                        tri_lerp(&[vertices[0].z, vertices[1].z, vertices[2].z], barycentric);
-                    let perspective =
-                        tri_lerp(&[vertices[0].w, vertices[1].w, vertices[2].w], barycentric);
-                    if 0.0 <= depth && depth <= 1.0 {
-                        let inv_perspective = 1.0 / perspective;
-                        let interpolation = [
-                            barycentric[0] * vertices[0].w * inv_perspective,
-                            barycentric[1] * vertices[1].w * inv_perspective,
-                            barycentric[2] * vertices[2].w * inv_perspective,
-                        ];
-
-                        let frag_data = S::FragmentInput::tri_lerp(&vertex_data, interpolation);
-                        let color = shaders.fragment_shader(&frag_data);
-                        pixels.set(pixel, color);
-                    }
-                }
-            }
+        self.threads
+            .execute(TileJob::new(&mut tiles, shaders, &triangles));
+
+        for tile in &tiles {
+            tile.store_depth(Some(depth_buffer));
         }
+        statistics
     }
-}
 
-fn triangle_bounds(points: &[Vector4; 3]) -> (Vector3, Vector3) {
-    let mut min = points[0].truncate();
-    let mut max = points[0].truncate();
-    for point in points.iter().skip(1) {
-        min.x = min.x.min(point.x);
-        min.y = min.y.min(point.y);
-        min.z = min.z.min(point.z);
-        max.x = max.x.max(point.x);
-        max.y = max.y.max(point.y);
-        max.z = max.z.max(point.z);
-    }
-    (min, max)
-}
+    /// Draw to a multisampled framebuffer, testing coverage and depth at every sample but running
+    /// the fragment shader once per pixel.
+    pub fn draw_multisampled<S, V>(
+        &self,
+        target: &mut MultisampleBuffer,
+        shaders: &S,
+        vertex_buffer: &V,
+        indices: &[Triangle<VertexIndex>],
+    ) -> DrawStatistics
+    where
+        S: ShaderModule,
+        E: TileExecutor<S>,
+        V: VertexBuffer<S::VertexInput>,
+    {
+        assert_eq!(
+            S::Output::COUNT,
+            1,
+            "multisampled framebuffers only have a single color attachment"
+        );
+
+        let size = target.size();
+        let (triangles, statistics) =
+            raster::shade_triangles(shaders, vertex_buffer, indices, size, self, true, true);
+
+        let mut tiles = raster::bin_primitives(size, &triangles);
+        for tile in &mut tiles {
+            tile.load_multisampled(target, self);
+        }
+
+        self.threads
+            .execute(TileJob::new(&mut tiles, shaders, &triangles));
 
-fn triangle_winding_order([a, b, c]: [Vector2; 3]) -> WindingOrder {
-    let side_area = |current: Vector2, next: Vector2| (next.x - current.x) * (next.y + current.y);
-    let areas = [side_area(a, b), side_area(b, c), side_area(c, a)];
-    let total_area = areas.iter().sum::<f32>();
-    if total_area > 0.0 {
-        WindingOrder::Clockwise
-    } else {
-        WindingOrder::CounterClockwise
+        for tile in &tiles {
+            tile.store_multisampled(target);
+        }
+        statistics
     }
-}
 
-#[inline(always)]
-fn triangle_area([a, b, c]: [Vector2; 3]) -> f32 {
-    (a - c).cross(b - c)
-}
+    fn rasterize<P, S>(
+        &self,
+        pixels: &mut P,
+        mut depth_buffer: Option<&mut DepthBuffer>,
+        shaders: &S,
+        primitives: &[raster::Primitive<S::FragmentInput>],
+    ) where
+        P: RenderTarget,
+        S: ShaderModule,
+        E: TileExecutor<S>,
+    {
+        let size = pixels.target_size();
+        assert_eq!(
+            S::Output::COUNT,
+            pixels.attachment_count(),
+            "number of fragment shader outputs does not match number of render target attachments"
+        );
+        if let Some(depth_buffer) = &depth_buffer {
+            assert_eq!(
+                depth_buffer.size, size,
+                "depth buffer size does not match pixel buffer size"
+            );
+        }
 
-#[inline(always)]
-fn barycentric_coords([a, b, c]: [Vector2; 3], point: Vector2) -> Option<[f32; 3]> {
-    let a_area = triangle_area([point, b, c]);
-    let b_area = triangle_area([a, point, c]);
-    let c_area = triangle_area([a, b, point]);
+        let mut tiles = raster::bin_primitives(size, primitives);
+        for tile in &mut tiles {
+            tile.load::<S, _, _>(pixels, depth_buffer.as_deref(), self);
+        }
 
-    let a_sign = a_area.is_sign_positive();
-    let b_sign = b_area.is_sign_positive();
-    let c_sign = c_area.is_sign_positive();
+        self.threads
+            .execute(TileJob::new(&mut tiles, shaders, primitives));
 
-    if a_sign == b_sign && b_sign == c_sign {
-        let inv_area = 1.0 / triangle_area([a, b, c]);
-        Some([a_area * inv_area, b_area * inv_area, c_area * inv_area])
-    } else {
-        None
+        for tile in &tiles {
+            tile.store(pixels, depth_buffer.as_deref_mut());
+        }
     }
 }
 
+/// Draw triangles on the calling thread using the default [`Rasterizer`].
+pub fn draw<P, S, V>(
+    pixels: &mut P,
+    depth_buffer: Option<&mut DepthBuffer>,
+    shaders: &S,
+    vertex_buffer: &V,
+    indices: &[Triangle<VertexIndex>],
+) -> DrawStatistics
+where
+    P: RenderTarget,
+    S: ShaderModule,
+    V: VertexBuffer<S::VertexInput>,
+{
+    Rasterizer::default().draw(pixels, depth_buffer, shaders, vertex_buffer, indices)
+}
+
 pub fn tri_lerp<T>(values: &[T; 3], factors: [f32; 3]) -> T
 where
     T: Add<T, Output = T> + Copy + Mul<f32, Output = T>,
@@ -266,10 +658,10 @@ where
     values[0] * factors[0] + values[1] * factors[1] + values[2] * factors[2]
 }
 
-impl_elementwise_op!(Color { r, g, b }, Add, add);
-impl_elementwise_op!(Color { r, g, b }, Sub, sub);
-impl_elementwise_op!(Color { r, g, b }, Mul, mul);
-impl_scalar_op!(Color { r, g, b }, Mul<f32>, mul);
+impl_elementwise_op!(Color { r, g, b, a }, Add, add);
+impl_elementwise_op!(Color { r, g, b, a }, Sub, sub);
+impl_elementwise_op!(Color { r, g, b, a }, Mul, mul);
+impl_scalar_op!(Color { r, g, b, a }, Mul<f32>, mul);
 
 impl Interpolate for () {
     fn tri_lerp(_values: &[Self; 3], _factors: [f32; 3]) -> Self {}
@@ -305,55 +697,264 @@ impl<T: Clone> VertexBuffer<T> for Vec<T> {
     }
 }
 
-impl<T> Texture<T> {
-    pub fn new(pixels: Vec<T>, size: Dimensions) -> Texture<T> {
-        assert_eq!(pixels.len(), size.width as usize * size.height as usize);
-
-        Texture { size, pixels }
+impl Viewport {
+    /// A viewport covering a framebuffer of the given size, with the full depth range.
+    pub fn new(size: Dimensions) -> Viewport {
+        Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: size.width as f32,
+            height: size.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
     }
 }
 
-impl<T: Clone + Default> Texture<T> {
-    pub fn sample_nearest(&self, coord: Vector2) -> T {
-        if 0.0 <= coord.x && coord.x < 1.0 && 0.0 <= coord.y && coord.y < 1.0 {
-            let x = coord.x * (self.size.width) as f32;
-            let y = coord.y * (self.size.height) as f32;
-            let index = x as usize + y as usize * self.size.width as usize;
-            self.pixels[index].clone()
-        } else {
-            T::default()
+impl DepthBuffer {
+    /// Create a new depth buffer where every pixel is cleared to the far plane (`1.0`).
+    pub fn new(size: Dimensions) -> DepthBuffer {
+        DepthBuffer {
+            size,
+            depths: vec![1.0; size.width as usize * size.height as usize],
+            stencil: None,
         }
     }
 
-    pub fn sample_nearest_clamp(&self, coord: Vector2) -> T {
-        let clamped = Vector2 {
-            x: coord.x.max(0.0).min(1.0 - f32::EPSILON),
-            y: coord.y.max(0.0).min(1.0 - f32::EPSILON),
-        };
-        self.sample_nearest(clamped)
+    /// Like [`new`](DepthBuffer::new), but also with a stencil value for every pixel, cleared to
+    /// zero.
+    pub fn with_stencil(size: Dimensions) -> DepthBuffer {
+        DepthBuffer {
+            stencil: Some(vec![0; size.width as usize * size.height as usize]),
+            ..DepthBuffer::new(size)
+        }
     }
 
-    pub fn sample_nearest_repeat(&self, coord: Vector2) -> T {
-        let repeated = Vector2 {
-            x: coord.x - coord.x.floor(),
-            y: coord.y - coord.y.floor(),
-        };
-        self.sample_nearest(repeated)
+    pub fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    /// Set the depth of every pixel in the buffer
+    pub fn clear(&mut self, depth: f32) {
+        self.depths.iter_mut().for_each(|value| *value = depth);
+    }
+
+    pub fn get(&self, pixel: PixelCoord) -> f32 {
+        self.depths[self.index(pixel)]
+    }
+
+    pub fn set(&mut self, pixel: PixelCoord, depth: f32) {
+        let index = self.index(pixel);
+        self.depths[index] = depth;
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.stencil.is_some()
+    }
+
+    /// Set the stencil value of every pixel in the buffer.
+    ///
+    /// Panics if the buffer was created without a stencil attachment.
+    pub fn clear_stencil(&mut self, value: u8) {
+        self.stencil_values_mut()
+            .iter_mut()
+            .for_each(|stored| *stored = value);
+    }
+
+    /// Panics if the buffer was created without a stencil attachment.
+    pub fn get_stencil(&self, pixel: PixelCoord) -> u8 {
+        let index = self.index(pixel);
+        match &self.stencil {
+            Some(stencil) => stencil[index],
+            None => panic!("depth buffer has no stencil attachment"),
+        }
+    }
+
+    /// Panics if the buffer was created without a stencil attachment.
+    pub fn set_stencil(&mut self, pixel: PixelCoord, value: u8) {
+        let index = self.index(pixel);
+        self.stencil_values_mut()[index] = value;
+    }
+
+    fn stencil_values_mut(&mut self) -> &mut [u8] {
+        self.stencil
+            .as_mut()
+            .expect("depth buffer has no stencil attachment")
+    }
+
+    #[inline(always)]
+    fn index(&self, pixel: PixelCoord) -> usize {
+        debug_assert!(pixel.x < self.size.width, "pixel out of bounds");
+        debug_assert!(pixel.y < self.size.height, "pixel out of bounds");
+        pixel.x as usize + pixel.y as usize * self.size.width as usize
+    }
+}
+
+impl CompareFunction {
+    /// Compare the depth or stencil value of a fragment against the value currently stored in the
+    /// buffer.
+    #[inline(always)]
+    pub fn test<T: PartialOrd>(self, value: T, stored: T) -> bool {
+        match self {
+            CompareFunction::Never => false,
+            CompareFunction::Less => value < stored,
+            CompareFunction::Equal => value == stored,
+            CompareFunction::LessEqual => value <= stored,
+            CompareFunction::Greater => value > stored,
+            CompareFunction::NotEqual => value != stored,
+            CompareFunction::GreaterEqual => value >= stored,
+            CompareFunction::Always => true,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn depth_test_keeps_closest_fragment() {
+        let mut pixels = TestBuffer::new(4, 4);
+        let mut depth = DepthBuffer::new(pixels.size);
+
+        let near = quad(0.25, Color::from([1.0, 0.0, 0.0]));
+        let far = quad(0.75, Color::from([0.0, 1.0, 0.0]));
+        draw(&mut pixels, Some(&mut depth), &FlatShaders, &near, &QUAD);
+        draw(&mut pixels, Some(&mut depth), &FlatShaders, &far, &QUAD);
+
+        for color in &pixels.pixels {
+            assert_eq!(*color, Color::from([1.0, 0.0, 0.0]));
+        }
+        assert_eq!(depth.get(PixelCoord { x: 1, y: 2 }), 0.25);
+    }
+
+    struct CountingShaders {
+        invocations: std::cell::Cell<usize>,
+    }
+
+    impl ShaderModule for CountingShaders {
+        type VertexInput = (Vector4, Color);
+        type FragmentInput = Color;
+        type Output = Color;
+
+        fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+            self.invocations.set(self.invocations.get() + 1);
+            *vertex
+        }
+
+        fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> Color {
+            *fragment.input()
+        }
+    }
 
     #[test]
-    fn winding_order() {
-        let triangle = [
-            Vector2::new(1.0, 2.0),
-            Vector2::new(2.0, 2.0),
-            Vector2::new(1.0, 1.0),
+    fn shared_vertices_are_shaded_once() {
+        let mut pixels = TestBuffer::new(4, 4);
+        let shaders = CountingShaders {
+            invocations: Default::default(),
+        };
+
+        let vertices = quad(0.5, Color::WHITE);
+        let indices = [QUAD[0], QUAD[1], [0, 2, 3].into()];
+        let statistics = draw(&mut pixels, None, &shaders, &vertices, &indices);
+
+        assert_eq!(
+            statistics,
+            DrawStatistics {
+                vertex_shader_invocations: 4,
+                vertex_cache_hits: 5,
+            }
+        );
+        assert_eq!(shaders.invocations.into_inner(), 4);
+        assert!(pixels.pixels.iter().all(|&color| color == Color::WHITE));
+    }
+
+    struct DerivativeShaders;
+
+    impl ShaderModule for DerivativeShaders {
+        type VertexInput = Vector4;
+        type FragmentInput = Vector2;
+        type Output = Color;
+
+        fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+            (*vertex, Vector2::from(*vertex))
+        }
+
+        fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> Color {
+            let dx = fragment.dfdx(|position| *position);
+            let dy = fragment.dfdy(|position| *position);
+            Color::from([dx.x, dx.y, dy.x, dy.y])
+        }
+    }
+
+    #[test]
+    fn screen_space_derivatives() {
+        let mut pixels = TestBuffer::new(5, 4);
+
+        // only covers the top left corner of the framebuffer
+        let vertices = vec![
+            Vector4::new(-1.0, 1.0, 0.5, 1.0),
+            Vector4::new(1.0, 1.0, 0.5, 1.0),
+            Vector4::new(-1.0, -1.0, 0.5, 1.0),
         ];
+        draw(
+            &mut pixels,
+            None,
+            &DerivativeShaders,
+            &vertices,
+            &[[0, 1, 2].into()],
+        );
+
+        let expected = Color::from([2.0 / 5.0, 0.0, 0.0, -2.0 / 4.0]);
+        let covered = [
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (0, 1),
+            (1, 1),
+            (2, 1),
+            (0, 2),
+        ];
+        for (x, y) in covered.iter().copied() {
+            let color = pixels.get(PixelCoord { x, y });
+            assert!(
+                (color - expected).to_arr().iter().all(|c| c.abs() < 1e-5),
+                "{:?} at ({}, {})",
+                color,
+                x,
+                y
+            );
+        }
+    }
 
-        assert_eq!(triangle_winding_order(triangle), WindingOrder::Clockwise);
+    #[test]
+    fn derivatives_of_points_are_zero() {
+        let mut pixels = TestBuffer::new(8, 8);
+
+        let vertices = vec![
+            screen_position(1.0, 1.0),
+            screen_position(6.0, 1.0),
+            screen_position(1.0, 6.0),
+        ];
+        let rasterizer = Rasterizer {
+            polygon_mode: PolygonMode::Point,
+            point_size: 2.0,
+            ..Rasterizer::default()
+        };
+        rasterizer.draw(
+            &mut pixels,
+            None,
+            &DerivativeShaders,
+            &vertices,
+            &[[0, 1, 2].into()],
+        );
+
+        let covered = pixels.covered();
+        assert_eq!(covered.len(), 3 * 4);
+        for (x, y) in covered {
+            assert_eq!(pixels.get(PixelCoord { x, y }), Color::TRANSPARENT);
+        }
     }
 }
@@ -0,0 +1,183 @@
+//! Triangle meshes loaded from model files, ready to be drawn.
+
+mod gltf;
+mod json;
+mod obj;
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::{Color, ImageError, Matrix4, Texture, Triangle, Vector2, Vector3, VertexIndex};
+
+/// A vertex of a loaded mesh. Attributes missing from the file are zero.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct MeshVertex {
+    pub position: Vector3,
+    pub normal: Vector3,
+    /// Texture coordinates, with the origin in the top left corner of the texture like
+    /// [`Texture`](crate::Texture).
+    pub tex_coord: Vector2,
+}
+
+/// Vertices and the triangles connecting them, which can be passed directly to
+/// [`draw`](crate::draw).
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub triangles: Vec<Triangle<VertexIndex>>,
+    /// Consecutive runs of triangles that belong to the same group and share a material.
+    pub groups: Vec<MeshGroup>,
+    pub materials: Vec<Material>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshGroup {
+    pub name: String,
+    /// Index of the material in [`Mesh::materials`].
+    pub material: Option<usize>,
+    /// The triangles in [`Mesh::triangles`] that belong to the group.
+    pub triangles: Range<usize>,
+}
+
+/// Surface properties of a mesh, as described by the Phong reflection model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub ambient: Color,
+    /// The base color of the surface, with its alpha set to the opacity of the material.
+    pub diffuse: Color,
+    pub specular: Color,
+    pub emissive: Color,
+    /// Exponent of the specular highlight.
+    pub shininess: f32,
+    pub diffuse_texture: Option<PathBuf>,
+    pub specular_texture: Option<PathBuf>,
+    pub normal_texture: Option<PathBuf>,
+    /// The base color texture, as an index into [`Scene::textures`] for materials loaded as
+    /// part of a scene.
+    pub texture: Option<usize>,
+}
+
+/// Meshes placed in the world, along with the textures used by their materials.
+#[derive(Clone, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    /// Every occurrence of a mesh in the scene. A mesh may be drawn any number of times.
+    pub instances: Vec<MeshInstance>,
+    pub textures: Vec<Texture<Color>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshInstance {
+    /// Index of the mesh in [`Scene::meshes`].
+    pub mesh: usize,
+    /// Transforms the vertices of the mesh into world space.
+    pub transform: Matrix4,
+}
+
+/// The reasons a mesh could not be loaded.
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    /// The file is not valid according to its format.
+    Parse {
+        /// The line the error occurred on, or `0` if the format is not line based.
+        line: usize,
+        message: String,
+    },
+    /// The file uses a feature of its format that is not supported.
+    Unsupported(String),
+    /// An image referenced by the file could not be loaded.
+    Image(ImageError),
+}
+
+impl Mesh {
+    /// Load a mesh from a Wavefront OBJ file, along with the materials in any MTL files it
+    /// references. Texture paths in the materials are resolved relative to the MTL file.
+    pub fn load_obj(path: impl AsRef<Path>) -> Result<Mesh, MeshError> {
+        obj::load(path.as_ref())
+    }
+
+    /// Parse a mesh in the Wavefront OBJ format. Material libraries are not loaded, so materials
+    /// only carry the name they are referred to by.
+    pub fn parse_obj(source: &str) -> Result<Mesh, MeshError> {
+        obj::parse(source, |_| Ok(Vec::new()))
+    }
+
+    /// The triangles belonging to a group.
+    pub fn group_triangles(&self, group: &MeshGroup) -> &[Triangle<VertexIndex>] {
+        &self.triangles[group.triangles.clone()]
+    }
+}
+
+impl Scene {
+    /// Load the default scene of a glTF 2.0 file, in either the `.gltf` or binary `.glb`
+    /// container. Buffers and images may be embedded or stored in files relative to the scene.
+    pub fn load_gltf(path: impl AsRef<Path>) -> Result<Scene, MeshError> {
+        gltf::load(path.as_ref())
+    }
+
+    /// Parse a glTF 2.0 file. All buffers and images must be embedded, since there is no path to
+    /// resolve external files against.
+    pub fn parse_gltf(data: &[u8]) -> Result<Scene, MeshError> {
+        gltf::parse(data, &mut |uri| {
+            Err(MeshError::Unsupported(format!(
+                "reference to external file `{}`",
+                uri
+            )))
+        })
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::new(),
+            ambient: Color::BLACK,
+            diffuse: Color::WHITE,
+            specular: Color::BLACK,
+            emissive: Color::BLACK,
+            shininess: 0.0,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+            texture: None,
+        }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::Io(error) => write!(f, "{}", error),
+            MeshError::Parse { line: 0, message } => write!(f, "{}", message),
+            MeshError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MeshError::Unsupported(feature) => write!(f, "unsupported: {}", feature),
+            MeshError::Image(error) => write!(f, "failed to load image: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Io(error) => Some(error),
+            MeshError::Image(error) => Some(error),
+            MeshError::Parse { .. } | MeshError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for MeshError {
+    fn from(error: io::Error) -> Self {
+        MeshError::Io(error)
+    }
+}
+
+impl From<ImageError> for MeshError {
+    fn from(error: ImageError) -> Self {
+        MeshError::Image(error)
+    }
+}
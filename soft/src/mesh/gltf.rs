@@ -0,0 +1,888 @@
+//! glTF 2.0 scenes, in both the `.gltf` (JSON) and `.glb` (binary) containers. See
+//! <https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html>.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::json::Json;
+use super::{Material, Mesh, MeshError, MeshGroup, MeshInstance, MeshVertex, Scene};
+use crate::{
+    Color, Image, ImageFormat, Matrix4, Texture, Topology, Triangle, Vector2, Vector3, Vector4,
+};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4e4f_534a;
+const GLB_CHUNK_BIN: u32 = 0x004e_4942;
+
+const MODE_TRIANGLES: usize = 4;
+const MODE_TRIANGLE_STRIP: usize = 5;
+const MODE_TRIANGLE_FAN: usize = 6;
+
+/// The most elements an accessor without a buffer view may have. Such accessors are filled with
+/// zeros, so their count is not limited by the size of a buffer.
+const MAX_ZEROED_ELEMENTS: usize = 1 << 24;
+
+type LoadFile<'a> = dyn FnMut(&str) -> Result<Vec<u8>, MeshError> + 'a;
+
+pub(super) fn load(path: &Path) -> Result<Scene, MeshError> {
+    let data = std::fs::read(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&data, &mut |uri| {
+        Ok(std::fs::read(directory.join(percent_decode(uri)?))?)
+    })
+}
+
+/// Parse a glTF or GLB file, loading the external files it references with the given function.
+pub(super) fn parse(data: &[u8], load_file: &mut LoadFile) -> Result<Scene, MeshError> {
+    let (json, binary) = if data.starts_with(GLB_MAGIC) {
+        split_glb(data)?
+    } else {
+        (data, None)
+    };
+    let json = std::str::from_utf8(json).map_err(|_| error("glTF JSON is not valid UTF-8"))?;
+    let json = Json::parse(json).map_err(error)?;
+
+    let version = json
+        .get("asset")
+        .and_then(|asset| asset.get("version"))
+        .and_then(Json::as_str)
+        .ok_or_else(|| error("missing asset version"))?;
+    if !version.starts_with("2.") {
+        return Err(unsupported(format!("glTF version {}", version)));
+    }
+    if let Some(extension) = array(&json, "extensionsRequired").first() {
+        let name = extension.as_str().unwrap_or_default();
+        return Err(unsupported(format!("glTF extension {}", name)));
+    }
+
+    let mut buffers = Vec::new();
+    for (index, buffer) in array(&json, "buffers").iter().enumerate() {
+        let data = match buffer.get("uri").and_then(Json::as_str) {
+            Some(uri) => read_uri(uri, load_file)?,
+            None if index == 0 => binary.ok_or_else(|| error("buffer without data"))?.to_vec(),
+            None => return Err(error("buffer without data")),
+        };
+        if data.len() < required_index(buffer, "byteLength")? {
+            return Err(error("buffer is shorter than its byte length"));
+        }
+        buffers.push(data);
+    }
+
+    let mut document = Document {
+        json: &json,
+        buffers,
+        load_file,
+        textures: HashMap::new(),
+        scene: Scene::default(),
+    };
+
+    let materials = array(&json, "materials")
+        .iter()
+        .map(|material| document.material(material))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for mesh in array(&json, "meshes") {
+        let mesh = document.mesh(mesh, &materials)?;
+        document.scene.meshes.push(mesh);
+    }
+
+    let nodes = array(&json, "nodes");
+    let roots = match optional_index(&json, "scene")?
+        .or_else(|| (!array(&json, "scenes").is_empty()).then_some(0))
+    {
+        Some(scene) => array(document.item("scenes", scene)?, "nodes").to_vec(),
+        // without scenes, every node that is not the child of another node is a root
+        None => {
+            let mut is_child = vec![false; nodes.len()];
+            for node in nodes {
+                for child in array(node, "children") {
+                    if let Some(child) = child.as_usize().and_then(|child| is_child.get_mut(child))
+                    {
+                        *child = true;
+                    }
+                }
+            }
+            (0..nodes.len())
+                .filter(|&node| !is_child[node])
+                .map(|node| Json::Number(node as f64))
+                .collect()
+        }
+    };
+    for root in &roots {
+        let root = root.as_usize().ok_or_else(|| error("invalid node index"))?;
+        document.node(root, Matrix4::identity(), 0)?;
+    }
+
+    Ok(document.scene)
+}
+
+/// Split a GLB file into its JSON and binary chunks.
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), MeshError> {
+    let word = |offset: usize| -> Result<u32, MeshError> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| error("unexpected end of GLB file"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let version = word(4)?;
+    if version != 2 {
+        return Err(unsupported(format!("GLB version {}", version)));
+    }
+    let length = (word(8)? as usize).min(data.len());
+
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset < length {
+        let chunk_length = word(offset)? as usize;
+        let kind = word(offset + 4)?;
+        let chunk = data
+            .get(offset + 8..)
+            .and_then(|rest| rest.get(..chunk_length))
+            .ok_or_else(|| error("unexpected end of GLB file"))?;
+        chunks.push((kind, chunk));
+        offset += 8 + chunk_length.div_ceil(4) * 4;
+    }
+
+    match chunks.as_slice() {
+        [(GLB_CHUNK_JSON, json), rest @ ..] => {
+            let binary = rest
+                .first()
+                .filter(|(kind, _)| *kind == GLB_CHUNK_BIN)
+                .map(|(_, chunk)| *chunk);
+            Ok((json, binary))
+        }
+        _ => Err(error("GLB file does not start with a JSON chunk")),
+    }
+}
+
+struct Document<'a, 'f> {
+    json: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    load_file: &'f mut LoadFile<'f>,
+    /// The index in `scene.textures` of every glTF texture that has been loaded.
+    textures: HashMap<usize, usize>,
+    scene: Scene,
+}
+
+/// A typed view of the elements in a buffer.
+struct Accessor<'a> {
+    /// The bytes spanned by the elements, empty if the accessor has no buffer view.
+    data: &'a [u8],
+    stride: usize,
+    count: usize,
+    component_type: usize,
+    components: usize,
+    normalized: bool,
+}
+
+impl<'a, 'f> Document<'a, 'f> {
+    fn item(&self, kind: &str, index: usize) -> Result<&'a Json, MeshError> {
+        array(self.json, kind)
+            .get(index)
+            .ok_or_else(|| error(format!("{} index {} out of bounds", kind, index)))
+    }
+
+    /// Get the bytes of a buffer view, and the distance between its elements.
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), MeshError> {
+        let view = self.item("bufferViews", index)?;
+        let buffer = required_index(view, "buffer")?;
+        let offset = index_or(view, "byteOffset", 0)?;
+        let length = required_index(view, "byteLength")?;
+        let stride = optional_index(view, "byteStride")?;
+
+        let data = self
+            .buffers
+            .get(buffer)
+            .and_then(|buffer| buffer.get(offset..offset.checked_add(length)?))
+            .ok_or_else(|| error("buffer view out of bounds"))?;
+        Ok((data, stride))
+    }
+
+    fn accessor(&self, index: usize) -> Result<Accessor<'_>, MeshError> {
+        let accessor = self.item("accessors", index)?;
+        if accessor.get("sparse").is_some() {
+            return Err(unsupported("sparse accessors"));
+        }
+
+        let component_type = required_index(accessor, "componentType")?;
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(error(format!("invalid component type {}", component_type))),
+        };
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(error("invalid accessor type")),
+        };
+        let count = required_index(accessor, "count")?;
+        let element_size = component_size * components;
+
+        let (data, stride) = match optional_index(accessor, "bufferView")? {
+            Some(view) => {
+                let (data, stride) = self.buffer_view(view)?;
+                let stride = stride.unwrap_or(element_size);
+                if stride < element_size {
+                    return Err(error("buffer view stride is smaller than an element"));
+                }
+
+                let offset = index_or(accessor, "byteOffset", 0)?;
+                let size = match count {
+                    0 => Some(0),
+                    _ => (count - 1)
+                        .checked_mul(stride)
+                        .and_then(|size| size.checked_add(element_size)),
+                };
+                let data = size
+                    .and_then(|size| data.get(offset..offset.checked_add(size)?))
+                    .ok_or_else(|| error("accessor out of bounds"))?;
+                (data, stride)
+            }
+            // accessors without a buffer view are filled with zeros
+            None if count > MAX_ZEROED_ELEMENTS => {
+                return Err(error(format!(
+                    "accessor without a buffer view has {} elements",
+                    count
+                )))
+            }
+            None => (&[][..], 0),
+        };
+
+        Ok(Accessor {
+            data,
+            stride,
+            count,
+            component_type,
+            components,
+            normalized: accessor
+                .get("normalized")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+        })
+    }
+
+    /// Load the texture of a material into the scene, and get its index in the scene.
+    fn texture(&mut self, texture: usize) -> Result<usize, MeshError> {
+        if let Some(&index) = self.textures.get(&texture) {
+            return Ok(index);
+        }
+
+        let source = optional_index(self.item("textures", texture)?, "source")?
+            .ok_or_else(|| unsupported("textures without a source image"))?;
+        let image = self.item("images", source)?;
+        let data = match image.get("uri").and_then(Json::as_str) {
+            Some(uri) => read_uri(uri, self.load_file)?,
+            None => {
+                let view = required_index(image, "bufferView")?;
+                self.buffer_view(view)?.0.to_vec()
+            }
+        };
+
+        let format = match ImageFormat::from_signature(&data) {
+            Some(format) => format,
+            None if data.starts_with(&[0xff, 0xd8]) => return Err(unsupported("JPEG images")),
+            None => return Err(unsupported("image format")),
+        };
+        let image = Image::decode(&data, format)?;
+
+        self.scene.textures.push(Texture::from(image));
+        let index = self.scene.textures.len() - 1;
+        self.textures.insert(texture, index);
+        Ok(index)
+    }
+
+    fn material(&mut self, material: &Json) -> Result<Material, MeshError> {
+        let pbr = material.get("pbrMetallicRoughness");
+        let diffuse = match pbr {
+            Some(pbr) => numbers(pbr, "baseColorFactor", [1.0; 4])?,
+            None => [1.0; 4],
+        };
+        let texture = match pbr.and_then(|pbr| pbr.get("baseColorTexture")) {
+            Some(texture) => Some(self.texture(required_index(texture, "index")?)?),
+            None => None,
+        };
+
+        Ok(Material {
+            name: name(material),
+            diffuse: Color::from(diffuse),
+            emissive: Color::from(numbers(material, "emissiveFactor", [0.0; 3])?),
+            texture,
+            ..Material::default()
+        })
+    }
+
+    /// Merge the primitives of a mesh into a single mesh, with a group for every primitive. The
+    /// groups are named after the mesh, followed by the index of the primitive if there are
+    /// several.
+    fn mesh(&self, mesh: &Json, materials: &[Material]) -> Result<Mesh, MeshError> {
+        let mut output = Mesh::default();
+        let mut local_materials = HashMap::new();
+
+        let primitives = array(mesh, "primitives");
+        for (index, primitive) in primitives.iter().enumerate() {
+            let attributes = primitive
+                .get("attributes")
+                .ok_or_else(|| error("primitive without attributes"))?;
+            let attribute = |name: &str| -> Result<Option<Accessor>, MeshError> {
+                match optional_index(attributes, name)? {
+                    Some(accessor) => Ok(Some(self.accessor(accessor)?)),
+                    None => Ok(None),
+                }
+            };
+
+            let positions = attribute("POSITION")?
+                .ok_or_else(|| error("primitive without positions"))?
+                .vectors::<3>()?;
+            let count = positions.len();
+            let normals = attribute("NORMAL")?
+                .map(|normals| normals.vectors::<3>())
+                .transpose()?;
+            let tex_coords = attribute("TEXCOORD_0")?
+                .map(|tex_coords| tex_coords.vectors::<2>())
+                .transpose()?;
+            if normals
+                .as_ref()
+                .is_some_and(|normals| normals.len() != count)
+                || tex_coords
+                    .as_ref()
+                    .is_some_and(|coords| coords.len() != count)
+            {
+                return Err(error("vertex attributes have different counts"));
+            }
+
+            let first_vertex = output.vertices.len() as u32;
+            for (i, [x, y, z]) in positions.into_iter().enumerate() {
+                let normal = normals.as_ref().map_or([0.0; 3], |normals| normals[i]);
+                let tex_coord = tex_coords.as_ref().map_or([0.0; 2], |coords| coords[i]);
+                output.vertices.push(MeshVertex {
+                    position: Vector3::new(x, y, z),
+                    normal: Vector3::from(normal),
+                    tex_coord: Vector2::from(tex_coord),
+                });
+            }
+
+            let indices = match optional_index(primitive, "indices")? {
+                Some(accessor) => self.accessor(accessor)?.indices()?,
+                None => (0..count as u32).collect(),
+            };
+            if indices.iter().any(|&index| index as usize >= count) {
+                return Err(error("vertex index out of bounds"));
+            }
+
+            let start = output.triangles.len();
+            let mode = index_or(primitive, "mode", MODE_TRIANGLES)?;
+            let topology = match mode {
+                MODE_TRIANGLES => Topology::TriangleList,
+                MODE_TRIANGLE_STRIP => Topology::TriangleStrip,
+                MODE_TRIANGLE_FAN => Topology::TriangleFan,
+                _ => return Err(unsupported(format!("primitive mode {}", mode))),
+            };
+            output.triangles.extend(
+                topology
+                    .triangles(&indices)
+                    .into_iter()
+                    .map(|triangle| Triangle::from(triangle.vertices.map(|i| first_vertex + i))),
+            );
+
+            let material = match optional_index(primitive, "material")? {
+                Some(material) => {
+                    let next = local_materials.len();
+                    let local = *local_materials.entry(material).or_insert(next);
+                    if local == next {
+                        let material = materials
+                            .get(material)
+                            .ok_or_else(|| error("material index out of bounds"))?;
+                        output.materials.push(material.clone());
+                    }
+                    Some(local)
+                }
+                None => None,
+            };
+
+            let group_name = match primitives.len() {
+                1 => name(mesh),
+                _ => format!("{}.{}", name(mesh), index),
+            };
+            output.groups.push(MeshGroup {
+                name: group_name,
+                material,
+                triangles: start..output.triangles.len(),
+            });
+        }
+
+        Ok(output)
+    }
+
+    /// Add the meshes of a node and its children to the scene.
+    fn node(&mut self, index: usize, parent: Matrix4, depth: usize) -> Result<(), MeshError> {
+        let node = self.item("nodes", index)?;
+        if depth > array(self.json, "nodes").len() {
+            return Err(error("the node hierarchy contains a cycle"));
+        }
+
+        let transform = parent * local_transform(node)?;
+        if let Some(mesh) = optional_index(node, "mesh")? {
+            if mesh >= self.scene.meshes.len() {
+                return Err(error(format!("meshes index {} out of bounds", mesh)));
+            }
+            self.scene.instances.push(MeshInstance { mesh, transform });
+        }
+
+        for child in array(node, "children") {
+            let child = child
+                .as_usize()
+                .ok_or_else(|| error("invalid node index"))?;
+            self.node(child, transform, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Accessor<'_> {
+    /// Read every element as a vector with `N` components.
+    fn vectors<const N: usize>(&self) -> Result<Vec<[f32; N]>, MeshError> {
+        if self.components != N {
+            return Err(error(format!("expected a vector with {} components", N)));
+        }
+        Ok((0..self.count)
+            .map(|element| std::array::from_fn(|component| self.read(element, component)))
+            .collect())
+    }
+
+    fn indices(&self) -> Result<Vec<u32>, MeshError> {
+        if self.components != 1 || !matches!(self.component_type, 5121 | 5123 | 5125) {
+            return Err(error("indices must be unsigned integer scalars"));
+        }
+        Ok((0..self.count)
+            .map(|element| self.integer(element, 0))
+            .collect())
+    }
+
+    fn read(&self, element: usize, component: usize) -> f32 {
+        let value = self.integer(element, component);
+        match (self.component_type, self.normalized) {
+            (5126, _) => f32::from_bits(value),
+            (5120, false) => value as i8 as f32,
+            (5122, false) => value as i16 as f32,
+            (5120, true) => (value as i8 as f32 / 127.0).max(-1.0),
+            (5122, true) => (value as i16 as f32 / 32767.0).max(-1.0),
+            (5121, true) => value as f32 / 255.0,
+            (5123, true) => value as f32 / 65535.0,
+            (_, _) => value as f32,
+        }
+    }
+
+    /// The raw bits of a component.
+    fn integer(&self, element: usize, component: usize) -> u32 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        let size = match self.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            _ => 4,
+        };
+        let offset = element * self.stride + component * size;
+        let bytes = &self.data[offset..offset + size];
+        match *bytes {
+            [a] => a as u32,
+            [a, b] => u16::from_le_bytes([a, b]) as u32,
+            [a, b, c, d] => u32::from_le_bytes([a, b, c, d]),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The transform of a node relative to its parent.
+fn local_transform(node: &Json) -> Result<Matrix4, MeshError> {
+    if node.get("matrix").is_some() {
+        let m = numbers(node, "matrix", [0.0; 16])?;
+        let column = |i: usize| Vector4::new(m[4 * i], m[4 * i + 1], m[4 * i + 2], m[4 * i + 3]);
+        return Ok(Matrix4::from_cols([
+            column(0),
+            column(1),
+            column(2),
+            column(3),
+        ]));
+    }
+
+    let translation = numbers(node, "translation", [0.0; 3])?;
+    let [x, y, z, w] = numbers(node, "rotation", [0.0, 0.0, 0.0, 1.0])?;
+    let [sx, sy, sz] = numbers(node, "scale", [1.0; 3])?;
+
+    let rotation = Matrix4::from([
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+            0.0,
+        ],
+        [
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+            0.0,
+        ],
+        [
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+            0.0,
+        ],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    let scale = Matrix4::from([
+        [sx, 0.0, 0.0, 0.0],
+        [0.0, sy, 0.0, 0.0],
+        [0.0, 0.0, sz, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    Ok(Matrix4::translate(Vector3::from(translation)) * rotation * scale)
+}
+
+/// Read the data referenced by a URI, which is either embedded as base64 or a relative path.
+fn read_uri(uri: &str, load_file: &mut LoadFile) -> Result<Vec<u8>, MeshError> {
+    let data = match uri.strip_prefix("data:") {
+        Some(data) => data,
+        None => return load_file(uri),
+    };
+    match data.split_once(',') {
+        Some((header, data)) if header.ends_with(";base64") => decode_base64(data),
+        _ => Err(unsupported("data URI without base64 encoding")),
+    }
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, MeshError> {
+    let data = data.trim_end_matches('=').as_bytes();
+    let mut output = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for &byte in data {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(error("invalid base64 in data URI")),
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            output.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(output)
+}
+
+/// Decode `%XX` escapes in a relative URI.
+fn percent_decode(uri: &str) -> Result<String, MeshError> {
+    let mut bytes = Vec::with_capacity(uri.len());
+    let mut rest = uri.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let escape = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| error(format!("invalid escape in URI `{}`", uri)))?;
+        bytes.push(escape);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).map_err(|_| error(format!("invalid URI `{}`", uri)))
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or_default()
+}
+
+fn name(json: &Json) -> String {
+    json.get("name")
+        .and_then(Json::as_str)
+        .unwrap_or_default()
+        .to_owned()
+}
+
+fn optional_index(json: &Json, key: &str) -> Result<Option<usize>, MeshError> {
+    match json.get(key) {
+        Some(value) => match value.as_usize() {
+            Some(index) => Ok(Some(index)),
+            None => Err(error(format!("`{}` is not a non-negative integer", key))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn index_or(json: &Json, key: &str, default: usize) -> Result<usize, MeshError> {
+    Ok(optional_index(json, key)?.unwrap_or(default))
+}
+
+fn required_index(json: &Json, key: &str) -> Result<usize, MeshError> {
+    optional_index(json, key)?.ok_or_else(|| error(format!("missing `{}`", key)))
+}
+
+fn numbers<const N: usize>(
+    json: &Json,
+    key: &str,
+    default: [f32; N],
+) -> Result<[f32; N], MeshError> {
+    let values = match json.get(key) {
+        Some(values) => values,
+        None => return Ok(default),
+    };
+    let invalid = || error(format!("`{}` is not an array of {} numbers", key, N));
+    let values = values
+        .as_array()
+        .filter(|values| values.len() == N)
+        .ok_or_else(invalid)?;
+
+    let mut output = [0.0; N];
+    for (output, value) in output.iter_mut().zip(values) {
+        *output = value.as_f64().ok_or_else(invalid)? as f32;
+    }
+    Ok(output)
+}
+
+fn error(message: impl Into<String>) -> MeshError {
+    MeshError::Parse {
+        line: 0,
+        message: message.into(),
+    }
+}
+
+fn unsupported(feature: impl Into<String>) -> MeshError {
+    MeshError::Unsupported(feature.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pack a JSON document and binary buffer into a GLB file.
+    fn glb(json: &str, binary: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        let mut binary = binary.to_vec();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        binary.resize(binary.len().div_ceil(4) * 4, 0);
+
+        let mut data = b"glTF".to_vec();
+        let length = 12 + 8 + json.len() + 8 + binary.len();
+        for word in [2, length as u32, json.len() as u32, GLB_CHUNK_JSON] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&json);
+        for word in [binary.len() as u32, GLB_CHUNK_BIN] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&binary);
+        data
+    }
+
+    fn bytes<T: Copy, const N: usize>(values: &[T], to_le_bytes: fn(T) -> [u8; N]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|&value| to_le_bytes(value))
+            .collect()
+    }
+
+    #[test]
+    fn binary_scene_with_node_hierarchy() {
+        let mut binary = bytes(&[0u16, 1, 2, 0], u16::to_le_bytes);
+        let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        binary.extend(bytes(&positions, f32::to_le_bytes));
+        binary.extend(bytes(&[0u16, 0, 65535, 0, 0, 65535], u16::to_le_bytes));
+
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "translation": [1, 2, 3], "children": [1] },
+                { "mesh": 0, "rotation": [0, 0, 0.70710678, 0.70710678], "scale": [2, 2, 2] }
+            ],
+            "meshes": [{
+                "name": "triangle",
+                "primitives": [{
+                    "attributes": { "POSITION": 1, "TEXCOORD_0": 2 },
+                    "indices": 0,
+                    "material": 0
+                }]
+            }],
+            "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0, 0, 0.5] } }],
+            "buffers": [{ "byteLength": 56 }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 6 },
+                { "buffer": 0, "byteOffset": 8, "byteLength": 48 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5123, "count": 3, "type": "SCALAR" },
+                { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" },
+                {
+                    "bufferView": 1, "byteOffset": 36, "componentType": 5123,
+                    "normalized": true, "count": 3, "type": "VEC2"
+                }
+            ]
+        }"#;
+
+        let scene = Scene::parse_gltf(&glb(json, &binary)).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.instances.len(), 1);
+
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.triangles, vec![[0, 1, 2].into()]);
+        assert_eq!(mesh.vertices[1].position, Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.vertices[1].tex_coord, Vector2::new(1.0, 0.0));
+        assert_eq!(mesh.vertices[2].tex_coord, Vector2::new(0.0, 1.0));
+        assert_eq!(mesh.groups.len(), 1);
+        assert_eq!(mesh.groups[0].name, "triangle");
+        assert_eq!(mesh.groups[0].material, Some(0));
+        assert_eq!(mesh.materials[0].diffuse, Color::from([1.0, 0.0, 0.0, 0.5]));
+
+        // rotated a quarter turn, scaled by the child and then translated by the parent
+        let instance = scene.instances[0];
+        assert_eq!(instance.mesh, 0);
+        let point = instance.transform * Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let expected = Vector4::new(1.0, 4.0, 3.0, 1.0);
+        assert!((point - expected).length() < 1e-5, "{:?}", point);
+    }
+
+    #[test]
+    fn embedded_textures_and_strips() {
+        let image = Image::from_pixels([2, 1].into(), vec![Color::WHITE, Color::BLACK]);
+        let mut png = Vec::new();
+        image.write(&mut png, ImageFormat::Png).unwrap();
+
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "nodes": [{{ "mesh": 0, "matrix": [1,0,0,0, 0,1,0,0, 0,0,1,0, 5,6,7,1] }}],
+                "meshes": [{{
+                    "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "mode": 5, "material": 0 }}]
+                }}],
+                "materials": [{{ "pbrMetallicRoughness": {{ "baseColorTexture": {{ "index": 0 }} }} }}],
+                "textures": [{{ "source": 0 }}],
+                "images": [{{ "bufferView": 0, "mimeType": "image/png" }}],
+                "buffers": [{{ "byteLength": {length} }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": {length} }}],
+                "accessors": [{{ "componentType": 5126, "count": 4, "type": "VEC3" }}]
+            }}"#,
+            length = png.len()
+        );
+
+        let scene = Scene::parse_gltf(&glb(&json, &png)).unwrap();
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2].into(), [2, 1, 3].into()]);
+        assert_eq!(mesh.materials[0].texture, Some(0));
+        assert_eq!(scene.textures[0].size(), [2, 1].into());
+        assert_eq!(
+            scene.textures[0].sample_nearest(Vector2::new(0.25, 0.5)),
+            Color::WHITE
+        );
+
+        let origin = scene.instances[0].transform * Vector4::new(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(origin, Vector4::new(5.0, 6.0, 7.0, 1.0));
+    }
+
+    #[test]
+    fn groups_of_primitives_are_numbered() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "meshes": [{
+                "name": "quad",
+                "primitives": [
+                    { "attributes": { "POSITION": 0 } },
+                    { "attributes": { "POSITION": 0 } }
+                ]
+            }],
+            "accessors": [{ "componentType": 5126, "count": 3, "type": "VEC3" }]
+        }"#;
+
+        let scene = Scene::parse_gltf(json.as_bytes()).unwrap();
+        let groups = &scene.meshes[0].groups;
+        assert_eq!(groups[0].name, "quad.0");
+        assert_eq!(groups[1].name, "quad.1");
+        assert_eq!(groups[1].triangles, 1..2);
+    }
+
+    #[test]
+    fn data_uris() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8gd29ybGQ").unwrap(), b"hello world");
+        assert!(decode_base64("a$==").is_err());
+
+        let mut no_files = |_: &str| -> Result<Vec<u8>, MeshError> { unreachable!() };
+        let uri = "data:application/octet-stream;base64,AAEC";
+        assert_eq!(read_uri(uri, &mut no_files).unwrap(), [0, 1, 2]);
+        assert_eq!(percent_decode("my%20mesh.bin").unwrap(), "my mesh.bin");
+    }
+
+    #[test]
+    fn invalid_scenes() {
+        let parse = |json: &str| Scene::parse_gltf(json.as_bytes());
+        assert!(matches!(
+            parse(r#"{ "asset": { "version": "1.0" } }"#),
+            Err(MeshError::Unsupported(_))
+        ));
+        assert!(matches!(
+            parse(
+                r#"{ "asset": { "version": "2.0" }, "buffers": [{ "uri": "mesh.bin", "byteLength": 4 }] }"#
+            ),
+            Err(MeshError::Unsupported(_))
+        ));
+        assert!(matches!(
+            parse(r#"{ "asset": { "version": "2.0" }, "nodes": [{ "mesh": 3 }] }"#),
+            Err(MeshError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse(
+                r#"{ "asset": { "version": "2.0" }, "nodes": [{ "children": [0] }], "scenes": [{ "nodes": [0] }] }"#
+            ),
+            Err(MeshError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_accessors() {
+        let parse = |accessor: &str| {
+            let json = format!(
+                r#"{{
+                    "asset": {{ "version": "2.0" }},
+                    "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }} }}] }}],
+                    "buffers": [{{ "byteLength": 36 }}],
+                    "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+                    "accessors": [{}]
+                }}"#,
+                accessor
+            );
+            Scene::parse_gltf(&glb(&json, &[0; 36]))
+        };
+
+        assert!(
+            parse(r#"{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }"#)
+                .is_ok()
+        );
+        for accessor in [
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 1e30, "type": "VEC3" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "byteOffset": 1e30 }"#,
+            r#"{ "componentType": 5126, "count": 1e30, "type": "VEC3" }"#,
+        ] {
+            assert!(
+                matches!(parse(accessor), Err(MeshError::Parse { .. })),
+                "{}",
+                accessor
+            );
+        }
+    }
+}
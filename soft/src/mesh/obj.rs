@@ -0,0 +1,398 @@
+//! Wavefront OBJ meshes and MTL material libraries, see
+//! <http://paulbourke.net/dataformats/obj/> and <http://paulbourke.net/dataformats/mtl/>.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use super::{Material, Mesh, MeshError, MeshGroup, MeshVertex};
+use crate::{Color, Vector2, Vector3};
+
+/// Indices of the position, texture coordinate and normal of a face vertex.
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+pub(super) fn load(path: &Path) -> Result<Mesh, MeshError> {
+    let source = std::fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&source, |name| {
+        let path = directory.join(name);
+        let source = std::fs::read_to_string(&path)?;
+        parse_materials(&source, path.parent().unwrap_or(directory)).map_err(|error| match error {
+            MeshError::Parse { line, message } => MeshError::Parse {
+                line,
+                message: format!("{}: {}", path.display(), message),
+            },
+            error => error,
+        })
+    })
+}
+
+/// Parse an OBJ file, loading the material libraries it references with the given function.
+pub(super) fn parse(
+    source: &str,
+    mut load_materials: impl FnMut(&str) -> Result<Vec<Material>, MeshError>,
+) -> Result<Mesh, MeshError> {
+    let mut parser = Parser::default();
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| MeshError::Parse {
+            line: index + 1,
+            message,
+        };
+
+        let mut words = line.split_whitespace();
+        let result = match words.next() {
+            Some("v") => vector3(&mut words).map(|position| parser.positions.push(position)),
+            Some("vn") => vector3(&mut words).map(|normal| parser.normals.push(normal)),
+            Some("vt") => tex_coord(&mut words).map(|tex_coord| parser.tex_coords.push(tex_coord)),
+            Some("f") => parser.face(words),
+            Some("g") | Some("o") => {
+                parser.start_group(words.collect::<Vec<_>>().join(" "), parser.material);
+                Ok(())
+            }
+            Some("usemtl") => {
+                let name = words.collect::<Vec<_>>().join(" ");
+                let material = parser.material(&name);
+                parser.start_group(parser.group_name.clone(), Some(material));
+                Ok(())
+            }
+            Some("mtllib") => {
+                for library in words {
+                    parser.materials.extend(load_materials(library)?);
+                }
+                continue;
+            }
+            // smoothing groups, and any other statements, do not affect the triangles
+            _ => Ok(()),
+        };
+        result.map_err(error)?;
+    }
+
+    parser.start_group(String::new(), None);
+    let mut mesh = parser.mesh;
+    mesh.materials = parser.materials;
+    Ok(mesh)
+}
+
+/// Parse an MTL file, resolving texture paths relative to a directory.
+pub(super) fn parse_materials(source: &str, directory: &Path) -> Result<Vec<Material>, MeshError> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+
+        if keyword == "newmtl" {
+            materials.push(Material {
+                name: words.collect::<Vec<_>>().join(" "),
+                ..Material::default()
+            });
+            continue;
+        }
+
+        let error = |message: String| MeshError::Parse {
+            line: index + 1,
+            message,
+        };
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => return Err(error(format!("`{}` before `newmtl`", keyword))),
+        };
+
+        // texture maps may be preceded by options, so the file name is the last word
+        let texture = |words: SplitWhitespace| words.last().map(|file| directory.join(file));
+
+        let result = match keyword {
+            "Ka" => color(&mut words).map(|color| material.ambient = color),
+            "Kd" => color(&mut words).map(|color| {
+                material.diffuse = Color {
+                    a: material.diffuse.a,
+                    ..color
+                }
+            }),
+            "Ks" => color(&mut words).map(|color| material.specular = color),
+            "Ke" => color(&mut words).map(|color| material.emissive = color),
+            "Ns" => number(&mut words).map(|shininess| material.shininess = shininess),
+            "d" => number(&mut words).map(|opacity| material.diffuse.a = opacity),
+            "Tr" => number(&mut words).map(|transparency| material.diffuse.a = 1.0 - transparency),
+            "map_Kd" => {
+                material.diffuse_texture = texture(words);
+                Ok(())
+            }
+            "map_Ks" => {
+                material.specular_texture = texture(words);
+                Ok(())
+            }
+            "map_Bump" | "map_bump" | "bump" | "norm" => {
+                material.normal_texture = texture(words);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        result.map_err(error)?;
+    }
+
+    Ok(materials)
+}
+
+#[derive(Default)]
+struct Parser {
+    positions: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    tex_coords: Vec<Vector2>,
+    /// The index of every unique combination of attributes in the vertex buffer.
+    vertices: HashMap<VertexKey, u32>,
+    materials: Vec<Material>,
+    mesh: Mesh,
+    group_name: String,
+    material: Option<usize>,
+    /// The first triangle in the current group.
+    group_start: usize,
+}
+
+impl Parser {
+    /// Finish the current group, if it has any triangles, and start a new one.
+    fn start_group(&mut self, name: String, material: Option<usize>) {
+        let end = self.mesh.triangles.len();
+        if end > self.group_start {
+            self.mesh.groups.push(MeshGroup {
+                name: std::mem::take(&mut self.group_name),
+                material: self.material,
+                triangles: self.group_start..end,
+            });
+        }
+        self.group_name = name;
+        self.material = material;
+        self.group_start = end;
+    }
+
+    /// Find a material by name, adding an empty material if it has not been defined.
+    fn material(&mut self, name: &str) -> usize {
+        match self
+            .materials
+            .iter()
+            .position(|material| material.name == name)
+        {
+            Some(index) => index,
+            None => {
+                self.materials.push(Material {
+                    name: name.to_owned(),
+                    ..Material::default()
+                });
+                self.materials.len() - 1
+            }
+        }
+    }
+
+    /// Add a polygon, split into a fan of triangles.
+    fn face(&mut self, words: SplitWhitespace) -> Result<(), String> {
+        let mut polygon = Vec::new();
+        for word in words {
+            let key = self.vertex_key(word)?;
+            polygon.push(self.vertex(key));
+        }
+
+        if polygon.len() < 3 {
+            return Err(format!("face with {} vertices", polygon.len()));
+        }
+        for i in 1..polygon.len() - 1 {
+            let triangle = [polygon[0], polygon[i], polygon[i + 1]];
+            self.mesh.triangles.push(triangle.into());
+        }
+        Ok(())
+    }
+
+    /// Parse a face vertex of the form `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+    fn vertex_key(&self, word: &str) -> Result<VertexKey, String> {
+        let mut parts = word.split('/');
+        let mut index = |count: usize| -> Result<Option<usize>, String> {
+            match parts.next() {
+                None | Some("") => Ok(None),
+                Some(part) => {
+                    let index = part
+                        .parse::<i64>()
+                        .map_err(|_| format!("invalid index `{}`", part))?;
+                    // negative indices are relative to the end of the list
+                    let resolved = match index {
+                        0 => None,
+                        1.. => Some(index - 1),
+                        _ => Some(count as i64 + index),
+                    };
+                    match resolved {
+                        Some(resolved) if (0..count as i64).contains(&resolved) => {
+                            Ok(Some(resolved as usize))
+                        }
+                        _ => Err(format!("index {} out of bounds", index)),
+                    }
+                }
+            }
+        };
+
+        let position = index(self.positions.len())?
+            .ok_or_else(|| format!("face vertex `{}` without a position", word))?;
+        let tex_coord = index(self.tex_coords.len())?;
+        let normal = index(self.normals.len())?;
+        Ok((position, tex_coord, normal))
+    }
+
+    /// Get the index of a vertex, adding it to the vertex buffer if it has not been used before.
+    fn vertex(&mut self, key: VertexKey) -> u32 {
+        let Parser {
+            vertices,
+            mesh,
+            positions,
+            normals,
+            tex_coords,
+            ..
+        } = self;
+        *vertices.entry(key).or_insert_with(|| {
+            let (position, tex_coord, normal) = key;
+            mesh.vertices.push(MeshVertex {
+                position: positions[position],
+                normal: normal.map_or(Vector3::ORIGIN, |normal| normals[normal]),
+                tex_coord: tex_coord.map_or(Vector2::ORIGIN, |tex_coord| tex_coords[tex_coord]),
+            });
+            mesh.vertices.len() as u32 - 1
+        })
+    }
+}
+
+fn number(words: &mut SplitWhitespace) -> Result<f32, String> {
+    optional_number(words)?.ok_or_else(|| "expected a number".to_owned())
+}
+
+fn optional_number(words: &mut SplitWhitespace) -> Result<Option<f32>, String> {
+    words
+        .next()
+        .map(|word| {
+            word.parse()
+                .map_err(|_| format!("invalid number `{}`", word))
+        })
+        .transpose()
+}
+
+fn vector3(words: &mut SplitWhitespace) -> Result<Vector3, String> {
+    Ok(Vector3::new(number(words)?, number(words)?, number(words)?))
+}
+
+fn tex_coord(words: &mut SplitWhitespace) -> Result<Vector2, String> {
+    let u = number(words)?;
+    let v = optional_number(words)?.unwrap_or(0.0);
+    // OBJ places the origin of texture coordinates in the bottom left corner
+    Ok(Vector2::new(u, 1.0 - v))
+}
+
+fn color(words: &mut SplitWhitespace) -> Result<Color, String> {
+    let r = number(words)?;
+    // a single value is used for all channels
+    let g = optional_number(words)?.unwrap_or(r);
+    let b = optional_number(words)?.unwrap_or(r);
+    Ok(Color::from([r, g, b]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_attributes_are_deduplicated() {
+        let source = "
+            # a unit square
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 1
+            vn 0 0 1
+            f 1/1/1 2/1/1 3/2/1 4/2/1
+            f -4/1/1 -2/2/1 -1/1/1
+        ";
+        let mesh = Mesh::parse_obj(source).unwrap();
+
+        let indices = mesh
+            .triangles
+            .iter()
+            .map(|triangle| triangle.vertices)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, [[0, 1, 2], [0, 2, 3], [0, 2, 4]]);
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(
+            mesh.vertices[2],
+            MeshVertex {
+                position: Vector3::new(1.0, 1.0, 0.0),
+                normal: Vector3::new(0.0, 0.0, 1.0),
+                tex_coord: Vector2::new(1.0, 0.0),
+            }
+        );
+        assert_eq!(mesh.vertices[4].position, Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(mesh.vertices[4].tex_coord, Vector2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn groups_and_materials() {
+        let source = "
+            mtllib scene.mtl
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            g first
+            usemtl red
+            f 1 2 3
+            f 1 2 3
+            usemtl missing
+            f 1 2 3
+            g second
+            f 1 2 3
+        ";
+        let library = "
+            newmtl red
+            Kd 1 0 0
+            d 0.5
+            map_Kd -s 2 2 textures/red.png
+        ";
+        let mesh = parse(source, |name| {
+            assert_eq!(name, "scene.mtl");
+            parse_materials(library, Path::new("models"))
+        })
+        .unwrap();
+
+        assert_eq!(mesh.materials.len(), 2);
+        assert_eq!(mesh.materials[0].diffuse, Color::from([1.0, 0.0, 0.0, 0.5]));
+        assert_eq!(
+            mesh.materials[0].diffuse_texture.as_deref(),
+            Some(Path::new("models/textures/red.png"))
+        );
+        assert_eq!(mesh.materials[1].name, "missing");
+
+        let groups = mesh
+            .groups
+            .iter()
+            .map(|group| (group.name.as_str(), group.material, group.triangles.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [
+                ("first", Some(0), 0..2),
+                ("first", Some(1), 2..3),
+                ("second", Some(1), 3..4),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_report_the_line() {
+        let error = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n").unwrap_err();
+        assert!(
+            matches!(error, MeshError::Parse { line: 3, .. }),
+            "{}",
+            error
+        );
+
+        let error = Mesh::parse_obj("v 0 0 zero").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: invalid number `zero`");
+    }
+}
@@ -0,0 +1,154 @@
+//! Multisample anti-aliasing (MSAA). Coverage and depth are evaluated at multiple sample
+//! positions within every pixel, while the fragment shader is only run once per pixel. The
+//! samples are then averaged into the final image when the buffer is resolved.
+
+use crate::{Color, Dimensions, PixelBuffer, PixelCoord};
+
+/// Number of samples taken per pixel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SampleCount {
+    One,
+    Two,
+    Four,
+    Eight,
+}
+
+/// Color and depth buffers with multiple samples per pixel. Draw to it with
+/// [`Rasterizer::draw_multisampled`](crate::Rasterizer::draw_multisampled), then
+/// [`resolve`](MultisampleBuffer::resolve) it into a [`PixelBuffer`].
+#[derive(Clone)]
+pub struct MultisampleBuffer {
+    size: Dimensions,
+    samples: SampleCount,
+    /// The samples of every pixel are stored next to each other.
+    pub(crate) colors: Vec<Color>,
+    pub(crate) depths: Vec<f32>,
+}
+
+impl SampleCount {
+    pub fn count(self) -> usize {
+        self.pattern().len()
+    }
+
+    /// Offsets of the samples from the center of the pixel, in sixteenths of a pixel. These are
+    /// the standard sample patterns used by Direct3D and Vulkan.
+    pub(crate) fn pattern(self) -> &'static [(i64, i64)] {
+        match self {
+            SampleCount::One => &[(0, 0)],
+            SampleCount::Two => &[(4, 4), (-4, -4)],
+            SampleCount::Four => &[(-2, -6), (6, -2), (-6, 2), (2, 6)],
+            SampleCount::Eight => &[
+                (1, -3),
+                (-1, 3),
+                (5, 1),
+                (-3, -5),
+                (-5, 5),
+                (-7, -1),
+                (3, 7),
+                (7, -7),
+            ],
+        }
+    }
+}
+
+impl MultisampleBuffer {
+    /// Create a new buffer where every sample is cleared to black and the far plane (`1.0`).
+    pub fn new(size: Dimensions, samples: SampleCount) -> MultisampleBuffer {
+        let sample_count = size.width as usize * size.height as usize * samples.count();
+        MultisampleBuffer {
+            size,
+            samples,
+            colors: vec![Color::BLACK; sample_count],
+            depths: vec![1.0; sample_count],
+        }
+    }
+
+    pub fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    pub fn samples(&self) -> SampleCount {
+        self.samples
+    }
+
+    /// Set the color and depth of every sample in the buffer.
+    pub fn clear(&mut self, color: Color, depth: f32) {
+        self.colors.iter_mut().for_each(|value| *value = color);
+        self.depths.iter_mut().for_each(|value| *value = depth);
+    }
+
+    /// Write the average color of the samples in every pixel to the pixel buffer.
+    pub fn resolve<P: PixelBuffer>(&self, pixels: &mut P) {
+        assert_eq!(
+            pixels.size(),
+            self.size,
+            "pixel buffer size does not match multisample buffer size"
+        );
+
+        let count = self.samples.count();
+        let weight = 1.0 / count as f32;
+        for (index, samples) in self.colors.chunks_exact(count).enumerate() {
+            let sum = samples
+                .iter()
+                .fold(Color::TRANSPARENT, |sum, sample| sum + *sample);
+            pixels.set(self.pixel(index), sum * weight);
+        }
+    }
+
+    /// The index of the first sample in a pixel.
+    #[inline(always)]
+    pub(crate) fn index(&self, pixel: PixelCoord) -> usize {
+        debug_assert!(pixel.x < self.size.width, "pixel out of bounds");
+        debug_assert!(pixel.y < self.size.height, "pixel out of bounds");
+        (pixel.x as usize + pixel.y as usize * self.size.width as usize) * self.samples.count()
+    }
+
+    fn pixel(&self, index: usize) -> PixelCoord {
+        PixelCoord {
+            x: index as u32 % self.size.width,
+            y: index as u32 / self.size.width,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::Rasterizer;
+
+    #[test]
+    fn samples_lie_within_pixel() {
+        for samples in [SampleCount::Two, SampleCount::Four, SampleCount::Eight] {
+            let pattern = samples.pattern();
+            assert!(pattern
+                .iter()
+                .all(|&(x, y)| (-8..8).contains(&x) && (-8..8).contains(&y)));
+            assert_eq!(pattern.iter().map(|sample| sample.0).sum::<i64>(), 0);
+            assert_eq!(pattern.iter().map(|sample| sample.1).sum::<i64>(), 0);
+        }
+    }
+
+    #[test]
+    fn multisampling_averages_covered_samples() {
+        // covers the bottom left half of the pixel
+        let vertices = quad(0.5, Color::WHITE);
+
+        for samples in [SampleCount::Four, SampleCount::Eight] {
+            let mut target = MultisampleBuffer::new(Dimensions::from([1, 1]), samples);
+            Rasterizer::default().draw_multisampled(
+                &mut target,
+                &FlatShaders,
+                &vertices,
+                &[[0, 1, 3].into()],
+            );
+
+            let mut pixels = TestBuffer::new(1, 1);
+            target.resolve(&mut pixels);
+            assert_eq!(pixels.pixels[0], Color::from([0.5, 0.5, 0.5, 1.0]));
+
+            let written = target.depths.iter().filter(|&&depth| depth == 0.5).count();
+            assert_eq!(written, samples.count() / 2);
+        }
+    }
+}
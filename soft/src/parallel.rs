@@ -0,0 +1,139 @@
+//! Distribution of the tiles of the framebuffer over threads. Tiles do not overlap, so they can
+//! be rasterized in any order, and the output is the same regardless of the number of threads.
+
+use std::sync::Mutex;
+
+use crate::raster::{Primitive, Tile};
+use crate::ShaderModule;
+
+/// Rasterizes every tile on the calling thread. This places no extra requirements on the
+/// shaders.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SingleThreaded;
+
+/// Rasterizes the tiles on the given number of threads, including the calling thread. The shaders
+/// and the outputs of the vertex shader are shared between the threads, so they have to be
+/// [`Sync`]. Draw calls with fewer tiles than threads are rasterized on the calling thread, since
+/// starting the threads would take longer than rasterizing the tiles.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MultiThreaded(pub usize);
+
+/// Decides which threads the tiles of a draw call are rasterized on.
+pub trait TileExecutor<S: ShaderModule> {
+    /// Rasterize every tile in the job.
+    fn execute(&self, job: TileJob<S>);
+}
+
+/// The tiles of a draw call, together with the shaders and primitives they are rasterized with.
+pub struct TileJob<'a, S: ShaderModule> {
+    tiles: &'a mut [Tile],
+    shaders: &'a S,
+    primitives: &'a [Primitive<S::FragmentInput>],
+}
+
+impl MultiThreaded {
+    /// Use as many threads as there are available cores.
+    pub fn available() -> MultiThreaded {
+        MultiThreaded(std::thread::available_parallelism().map_or(1, |threads| threads.get()))
+    }
+}
+
+impl<'a, S: ShaderModule> TileJob<'a, S> {
+    pub(crate) fn new(
+        tiles: &'a mut [Tile],
+        shaders: &'a S,
+        primitives: &'a [Primitive<S::FragmentInput>],
+    ) -> Self {
+        TileJob {
+            tiles,
+            shaders,
+            primitives,
+        }
+    }
+
+    fn rasterize_serial(self) {
+        for tile in self.tiles {
+            tile.rasterize(self.shaders, self.primitives);
+        }
+    }
+}
+
+impl<S: ShaderModule> TileExecutor<S> for SingleThreaded {
+    fn execute(&self, job: TileJob<S>) {
+        job.rasterize_serial();
+    }
+}
+
+impl<S> TileExecutor<S> for MultiThreaded
+where
+    S: ShaderModule + Sync,
+    S::FragmentInput: Sync,
+{
+    fn execute(&self, job: TileJob<S>) {
+        let threads = self.0;
+        if threads <= 1 || job.tiles.len() < threads {
+            return job.rasterize_serial();
+        }
+
+        let (shaders, primitives) = (job.shaders, job.primitives);
+        let queue = Mutex::new(job.tiles.iter_mut());
+        let work = || loop {
+            let next = queue.lock().unwrap().next();
+            match next {
+                Some(tile) => tile.rasterize(shaders, primitives),
+                None => break,
+            }
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 1..threads {
+                scope.spawn(work);
+            }
+            work();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::{Color, DepthBuffer, Rasterizer, Vector4};
+
+    #[test]
+    fn parallel_rasterizer_matches_serial() {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut seed = 17u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        for i in 0..50 {
+            for _ in 0..3 {
+                let position =
+                    Vector4::new(4.0 * random() - 2.0, 4.0 * random() - 2.0, random(), 1.0);
+                let color = Color::from([random(), random(), random()]);
+                vertices.push((position, color));
+            }
+            indices.push([3 * i, 3 * i + 1, 3 * i + 2].into());
+        }
+
+        let render = |threads: MultiThreaded| {
+            let mut pixels = TestBuffer::new(300, 200);
+            let mut depth = DepthBuffer::new(pixels.size);
+            Rasterizer::default().with_threads(threads).draw(
+                &mut pixels,
+                Some(&mut depth),
+                &FlatShaders,
+                &vertices,
+                &indices,
+            );
+            (pixels.pixels, depth.depths)
+        };
+
+        let serial = render(MultiThreaded(1));
+        let parallel = render(MultiThreaded(4));
+        assert!(serial == parallel);
+    }
+}
@@ -0,0 +1,1714 @@
+//! Conversion of primitives into fragments. The framebuffer is split into square tiles, and every
+//! primitive is binned into the tiles its bounding box covers. Since tiles do not overlap they
+//! can then be rasterized independently of each other, and in parallel.
+
+use crate::clip::{self, Clipped};
+use crate::*;
+
+/// Width and height (in pixels) of the tiles the framebuffer is split into.
+const TILE_SIZE: u32 = 64;
+
+/// Number of fractional bits in the fixed-point screen coordinates vertices are snapped to.
+const SUBPIXEL_BITS: u32 = 8;
+const PIXEL: i64 = 1 << SUBPIXEL_BITS;
+const HALF_PIXEL: i64 = PIXEL / 2;
+
+/// The largest number of samples per pixel.
+const MAX_SAMPLES: usize = 8;
+
+/// Width and height (in pixels) of the blocks the hierarchical depth buffer stores the range of
+/// depths in.
+const DEPTH_BLOCK_SIZE: u32 = 8;
+
+/// A triangle, line or point after the perspective divide, ready to be rasterized.
+pub(crate) struct Primitive<F> {
+    /// Positions in normalized device coordinates, with `1 / w` stored in the `w` component.
+    /// Lines repeat their last vertex, and points their only vertex, so that all primitives can
+    /// be interpolated with the same barycentric weights.
+    vertices: [Vector4; 3],
+    data: [F; 3],
+    shape: Shape,
+    /// Selects the stencil operations used by the primitive. Lines and points are always front
+    /// facing.
+    front_facing: bool,
+    /// The first pixel covered by the primitive's bounding box.
+    min: PixelCoord,
+    /// One past the last pixel covered by the primitive's bounding box.
+    max: PixelCoord,
+}
+
+/// The area covered by a primitive, in fixed-point screen coordinates.
+#[derive(Debug, Copy, Clone)]
+enum Shape {
+    Triangle {
+        /// The edge functions of the edges opposite each vertex.
+        edges: [Edge; 3],
+        /// Twice the area of the triangle, in fixed-point units.
+        area: i64,
+        /// The edges or vertices drawn instead of the interior of the triangle, if any.
+        outline: Option<Outline>,
+    },
+    /// A line one pixel wide.
+    Line { start: (i64, i64), end: (i64, i64) },
+    /// A square centered on the point.
+    Point { center: (i64, i64), half_size: i64 },
+}
+
+/// The parts of a triangle drawn in [`PolygonMode::Line`] and [`PolygonMode::Point`].
+#[derive(Debug, Copy, Clone)]
+struct Outline {
+    points: [(i64, i64); 3],
+    /// Which of the edges going from vertex `i` to vertex `i + 1` lie on the boundary of the
+    /// polygon the triangle was clipped from. Other edges are not drawn, and neither are the
+    /// vertices they start at, so that every vertex and edge of the polygon is drawn once.
+    boundary: [bool; 3],
+    /// Half the size of the points drawn at the vertices, or `None` if the edges are drawn.
+    half_size: Option<i64>,
+}
+
+/// An edge function, which is positive for pixels on the inside of the edge, zero on the edge,
+/// and negative on the outside. Since the function is linear it can be stepped incrementally
+/// from one pixel to the next.
+#[derive(Debug, Copy, Clone)]
+struct Edge {
+    /// Value at the center of the pixel `(0, 0)`.
+    origin: i64,
+    /// Change in value when moving one pixel to the right.
+    step_x: i64,
+    /// Change in value when moving one pixel down.
+    step_y: i64,
+    /// Pixels exactly on the edge belong to the triangle only if the edge is a top or left edge,
+    /// so that pixels on edges shared by two triangles are covered exactly once. This is `0` for
+    /// top and left edges, and `1` otherwise: a pixel is covered if `value >= bias`.
+    bias: i64,
+}
+
+/// A rectangular region of the framebuffer, and the primitives that may cover it.
+pub(crate) struct Tile {
+    min: PixelCoord,
+    size: Dimensions,
+    primitives: Vec<u32>,
+    samples: SampleCount,
+    /// Colors of the fragments written to the samples in the tile for every attachment, `None` if
+    /// the sample was not covered. The samples of every pixel are stored next to each other.
+    colors: Vec<Vec<Option<Color>>>,
+    /// Colors in the framebuffer before drawing, only loaded if the fragments are blended or
+    /// masked.
+    destination: Vec<Vec<Color>>,
+    /// How fragments are blended with the destination, copied from the rasterizer.
+    blend: Option<BlendState>,
+    /// The channels fragments are written to, copied from the rasterizer.
+    color_write: ColorWriteMask,
+    depths: Option<Vec<f32>>,
+    /// Stencil values of the samples, only loaded if the stencil test is enabled.
+    stencil: Option<(StencilState, Vec<u8>)>,
+    /// Whether the fragment shader is run. If not only the depth and stencil values are written.
+    shade_fragments: bool,
+    /// A hierarchical depth buffer: the smallest and largest depth in every 8x8 block of the
+    /// tile, used to skip blocks where primitives are hidden. `None` if the block has been
+    /// written to since its range was computed. Empty if there is no depth buffer, or if the
+    /// stencil test needs to see every fragment.
+    depth_bounds: Vec<Option<(f32, f32)>>,
+}
+
+/// The part of the framebuffer primitives are drawn to.
+#[derive(Debug, Copy, Clone)]
+struct RenderArea {
+    viewport: Viewport,
+    /// The first pixel inside both the framebuffer and the scissor rectangle.
+    min: PixelCoord,
+    /// One past the last pixel inside both the framebuffer and the scissor rectangle.
+    max: PixelCoord,
+}
+
+/// Runs the vertex shader at most once for every vertex index in a draw call, and hands out
+/// copies of its output.
+struct VertexCache<'a, S: ShaderModule, V> {
+    shaders: &'a S,
+    vertex_buffer: &'a V,
+    /// The output of every vertex that has been shaded, indexed by the vertex index. Vertices
+    /// with an index past the end are shaded every time they are used.
+    outputs: Vec<Option<(Vector4, S::FragmentInput)>>,
+    invocations: usize,
+    hits: usize,
+}
+
+/// Run the vertex shader, and clip, project and cull the resulting triangles. Unless
+/// `cache_vertices` is set, vertices are shaded every time they are used. Unless `interpolate` is
+/// set the vertex data of clipped triangles is copied from the original vertices instead of being
+/// interpolated, for when no fragments are shaded.
+pub(crate) fn shade_triangles<S, V, E>(
+    shaders: &S,
+    vertex_buffer: &V,
+    indices: &[Triangle<VertexIndex>],
+    size: Dimensions,
+    rasterizer: &Rasterizer<E>,
+    cache_vertices: bool,
+    interpolate: bool,
+) -> (Vec<Primitive<S::FragmentInput>>, DrawStatistics)
+where
+    S: ShaderModule,
+    V: VertexBuffer<S::VertexInput>,
+{
+    let area = RenderArea::new(rasterizer, size);
+    let mut cache = if cache_vertices {
+        let vertices = indices.iter().flat_map(|triangle| triangle.vertices);
+        VertexCache::new(shaders, vertex_buffer, vertices)
+    } else {
+        VertexCache::uncached(shaders, vertex_buffer)
+    };
+    let mut triangles = Vec::with_capacity(indices.len());
+    let mut push_triangle = |vertices: [Vector4; 3], data, boundary| {
+        if let Some(triangle) =
+            Primitive::triangle::<S, E>(vertices, data, boundary, &area, rasterizer)
+        {
+            triangles.push(triangle);
+        }
+    };
+
+    for triangle in indices {
+        let (pos_0, data_0) = cache.get(triangle.vertices[0]);
+        let (pos_1, data_1) = cache.get(triangle.vertices[1]);
+        let (pos_2, data_2) = cache.get(triangle.vertices[2]);
+
+        let positions = [pos_0, pos_1, pos_2];
+        let vertex_datas = [data_0, data_1, data_2];
+
+        match clip::clip_triangle(positions) {
+            Clipped::Outside => {}
+            Clipped::Inside => push_triangle(positions.map(unproject), vertex_datas, [true; 3]),
+            Clipped::Polygon(polygon) => {
+                let count = polygon.triangles().count();
+                for (i, clipped) in polygon.triangles().enumerate() {
+                    let positions = clipped.map(|vertex| unproject(vertex.position));
+                    let datas = if interpolate {
+                        clipped
+                            .map(|vertex| S::FragmentInput::tri_lerp(&vertex_datas, vertex.weights))
+                    } else {
+                        vertex_datas.clone()
+                    };
+                    // the polygon is split into a fan, so only the outer edges of the first and
+                    // last triangles lie on its boundary
+                    push_triangle(positions, datas, [i == 0, true, i + 1 == count]);
+                }
+            }
+        }
+    }
+
+    (triangles, cache.statistics())
+}
+
+/// Run the vertex shader, and clip and project the resulting lines.
+pub(crate) fn shade_lines<S, V, E>(
+    shaders: &S,
+    vertex_buffer: &V,
+    indices: &[Line<VertexIndex>],
+    size: Dimensions,
+    rasterizer: &Rasterizer<E>,
+) -> (Vec<Primitive<S::FragmentInput>>, DrawStatistics)
+where
+    S: ShaderModule,
+    V: VertexBuffer<S::VertexInput>,
+{
+    let area = RenderArea::new(rasterizer, size);
+    let vertices = indices.iter().flat_map(|line| line.vertices);
+    let mut cache = VertexCache::new(shaders, vertex_buffer, vertices);
+    let mut lines = Vec::with_capacity(indices.len());
+    for line in indices {
+        let (pos_0, data_0) = cache.get(line.vertices[0]);
+        let (pos_1, data_1) = cache.get(line.vertices[1]);
+
+        let [start, end] = match clip::clip_line([pos_0, pos_1]) {
+            Some(clipped) => clipped,
+            None => continue,
+        };
+        let positions = [unproject(start.position), unproject(end.position)];
+        let data = if start.weights[0] == 1.0 && end.weights[1] == 1.0 {
+            [data_0, data_1.clone(), data_1]
+        } else {
+            let vertex_datas = [data_0, data_1.clone(), data_1];
+            let end = S::FragmentInput::tri_lerp(&vertex_datas, end.weights);
+            [
+                S::FragmentInput::tri_lerp(&vertex_datas, start.weights),
+                end.clone(),
+                end,
+            ]
+        };
+
+        if let Some(line) = Primitive::line(positions, data, &area) {
+            lines.push(line);
+        }
+    }
+    (lines, cache.statistics())
+}
+
+/// Run the vertex shader, and project the points that are inside the visible volume.
+pub(crate) fn shade_points<S, V, E>(
+    shaders: &S,
+    vertex_buffer: &V,
+    indices: &[VertexIndex],
+    size: Dimensions,
+    rasterizer: &Rasterizer<E>,
+) -> (Vec<Primitive<S::FragmentInput>>, DrawStatistics)
+where
+    S: ShaderModule,
+    V: VertexBuffer<S::VertexInput>,
+{
+    let area = RenderArea::new(rasterizer, size);
+    let point_size = rasterizer.point_size;
+    let mut cache = VertexCache::new(shaders, vertex_buffer, indices.iter().copied());
+    let mut points = Vec::with_capacity(indices.len());
+    for &index in indices {
+        let (position, data) = cache.get(index);
+        if !clip::point_visible(position) {
+            continue;
+        }
+
+        let data = [data.clone(), data.clone(), data];
+        if let Some(point) = Primitive::point(unproject(position), data, &area, point_size) {
+            points.push(point);
+        }
+    }
+    (points, cache.statistics())
+}
+
+/// Split the framebuffer into tiles, and assign every primitive to the tiles it may cover. Tiles
+/// not covered by any primitive are omitted.
+pub(crate) fn bin_primitives<F>(size: Dimensions, primitives: &[Primitive<F>]) -> Vec<Tile> {
+    let tiles_x = size.width.div_ceil(TILE_SIZE);
+    let tiles_y = size.height.div_ceil(TILE_SIZE);
+
+    let mut bins = vec![Vec::new(); tiles_x as usize * tiles_y as usize];
+    for (index, primitive) in primitives.iter().enumerate() {
+        for tile_y in primitive.min.y / TILE_SIZE..primitive.max.y.div_ceil(TILE_SIZE) {
+            for tile_x in primitive.min.x / TILE_SIZE..primitive.max.x.div_ceil(TILE_SIZE) {
+                bins[(tile_x + tile_y * tiles_x) as usize].push(index as u32);
+            }
+        }
+    }
+
+    bins.into_iter()
+        .enumerate()
+        .filter(|(_, primitives)| !primitives.is_empty())
+        .map(|(index, primitives)| {
+            let min = PixelCoord {
+                x: index as u32 % tiles_x * TILE_SIZE,
+                y: index as u32 / tiles_x * TILE_SIZE,
+            };
+            let size = Dimensions {
+                width: TILE_SIZE.min(size.width - min.x),
+                height: TILE_SIZE.min(size.height - min.y),
+            };
+            Tile {
+                min,
+                size,
+                primitives,
+                samples: SampleCount::One,
+                colors: Vec::new(),
+                destination: Vec::new(),
+                blend: None,
+                color_write: ColorWriteMask::ALL,
+                depths: None,
+                stencil: None,
+                shade_fragments: false,
+                depth_bounds: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+impl RenderArea {
+    fn new<E>(rasterizer: &Rasterizer<E>, size: Dimensions) -> RenderArea {
+        let (min, max) = match rasterizer.scissor {
+            Some(scissor) => (
+                PixelCoord {
+                    x: scissor.x.min(size.width),
+                    y: scissor.y.min(size.height),
+                },
+                PixelCoord {
+                    x: scissor.x.saturating_add(scissor.width).min(size.width),
+                    y: scissor.y.saturating_add(scissor.height).min(size.height),
+                },
+            ),
+            None => (
+                PixelCoord { x: 0, y: 0 },
+                PixelCoord {
+                    x: size.width,
+                    y: size.height,
+                },
+            ),
+        };
+
+        RenderArea {
+            viewport: rasterizer.viewport.unwrap_or_else(|| Viewport::new(size)),
+            min,
+            max,
+        }
+    }
+
+    /// Convert a position in normalized device coordinates to fixed-point screen coordinates in
+    /// the viewport, with the origin in the top left corner of the framebuffer and y pointing
+    /// down.
+    fn snap_to_grid(&self, vertex: Vector4) -> (i64, i64) {
+        let viewport = &self.viewport;
+        let x = viewport.x + (0.5 + 0.5 * vertex.x) * viewport.width;
+        let y = viewport.y + (0.5 - 0.5 * vertex.y) * viewport.height;
+        (
+            (x * PIXEL as f32).round() as i64,
+            (y * PIXEL as f32).round() as i64,
+        )
+    }
+
+    /// Map the depth of a vertex from `[0, 1]` to the depth range of the viewport.
+    fn map_depth(&self, mut vertex: Vector4) -> Vector4 {
+        let viewport = &self.viewport;
+        vertex.z = viewport.min_depth + vertex.z * (viewport.max_depth - viewport.min_depth);
+        vertex
+    }
+}
+
+impl<'a, S, V> VertexCache<'a, S, V>
+where
+    S: ShaderModule,
+    V: VertexBuffer<S::VertexInput>,
+{
+    /// A cache with room for every vertex up to the largest of the indices.
+    fn new(
+        shaders: &'a S,
+        vertex_buffer: &'a V,
+        indices: impl Iterator<Item = VertexIndex>,
+    ) -> Self {
+        let len = indices.max().map_or(0, |max| max as usize + 1);
+        VertexCache {
+            outputs: vec![None; len],
+            ..VertexCache::uncached(shaders, vertex_buffer)
+        }
+    }
+
+    /// A cache that shades every vertex each time it is used.
+    fn uncached(shaders: &'a S, vertex_buffer: &'a V) -> Self {
+        VertexCache {
+            shaders,
+            vertex_buffer,
+            outputs: Vec::new(),
+            invocations: 0,
+            hits: 0,
+        }
+    }
+
+    /// The output of the vertex shader for a vertex, shading it if it has not been used before.
+    fn get(&mut self, index: VertexIndex) -> (Vector4, S::FragmentInput) {
+        if let Some(Some(output)) = self.outputs.get(index as usize) {
+            self.hits += 1;
+            return output.clone();
+        }
+
+        let vertex = self.vertex_buffer.get_vertex(index);
+        let output = self.shaders.vertex_shader(&vertex);
+        self.invocations += 1;
+        if let Some(slot) = self.outputs.get_mut(index as usize) {
+            *slot = Some(output.clone());
+        }
+        output
+    }
+
+    fn statistics(&self) -> DrawStatistics {
+        DrawStatistics {
+            vertex_shader_invocations: self.invocations,
+            vertex_cache_hits: self.hits,
+        }
+    }
+}
+
+impl<F> Primitive<F> {
+    fn triangle<S: ShaderModule, E>(
+        mut vertices: [Vector4; 3],
+        mut data: [F; 3],
+        mut boundary: [bool; 3],
+        render_area: &RenderArea,
+        rasterizer: &Rasterizer<E>,
+    ) -> Option<Primitive<F>> {
+        // cull back faces, without culling counter-clockwise triangles are front facing
+        let front_order = S::FRONT_FACE.unwrap_or(WindingOrder::CounterClockwise);
+        let front_facing = triangle_winding_order(vertices.map(Vector2::from)) == front_order;
+        if S::FRONT_FACE.is_some() && !front_facing {
+            return None;
+        }
+
+        vertices = vertices.map(|vertex| render_area.map_depth(vertex));
+        let mut points = vertices.map(|vertex| render_area.snap_to_grid(vertex));
+        let mut area = orient_2d(points[0], points[1], points[2]);
+        if area == 0 {
+            return None;
+        }
+        if area < 0 {
+            // make sure the edge functions are positive on the inside of the triangle
+            vertices.swap(1, 2);
+            data.swap(1, 2);
+            points.swap(1, 2);
+            // the edges now go the other way around the triangle
+            boundary.swap(0, 2);
+            area = -area;
+        }
+
+        let [a, b, c] = points;
+        let edges = [Edge::new(b, c), Edge::new(c, a), Edge::new(a, b)];
+
+        let outline = match rasterizer.polygon_mode {
+            PolygonMode::Fill => None,
+            PolygonMode::Line => Some(None),
+            PolygonMode::Point => Some(Some(point_half_size(rasterizer.point_size)?)),
+        };
+        let outline = outline.map(|half_size| Outline {
+            points,
+            boundary,
+            half_size,
+        });
+
+        let padding = outline.and_then(|outline| outline.half_size).unwrap_or(0);
+        let min = (a.0.min(b.0).min(c.0), a.1.min(b.1).min(c.1));
+        let max = (a.0.max(b.0).max(c.0), a.1.max(b.1).max(c.1));
+        let (min, max) = pixel_bounds(
+            (min.0 - padding, min.1 - padding),
+            (max.0 + padding, max.1 + padding),
+            render_area,
+        )?;
+
+        Some(Primitive {
+            vertices,
+            data,
+            shape: Shape::Triangle {
+                edges,
+                area,
+                outline,
+            },
+            front_facing,
+            min,
+            max,
+        })
+    }
+
+    fn line(vertices: [Vector4; 2], data: [F; 3], area: &RenderArea) -> Option<Primitive<F>> {
+        let vertices = vertices.map(|vertex| area.map_depth(vertex));
+        let [start, end] = vertices.map(|vertex| area.snap_to_grid(vertex));
+        if start == end {
+            return None;
+        }
+
+        let min = (start.0.min(end.0), start.1.min(end.1));
+        let max = (start.0.max(end.0), start.1.max(end.1));
+        let (min, max) = pixel_bounds(min, max, area)?;
+
+        Some(Primitive {
+            vertices: [vertices[0], vertices[1], vertices[1]],
+            data,
+            shape: Shape::Line { start, end },
+            front_facing: true,
+            min,
+            max,
+        })
+    }
+
+    fn point(vertex: Vector4, data: [F; 3], area: &RenderArea, point_size: f32) -> Option<Self> {
+        let vertex = area.map_depth(vertex);
+        let center = area.snap_to_grid(vertex);
+        let half_size = point_half_size(point_size)?;
+
+        let min = (center.0 - half_size, center.1 - half_size);
+        let max = (center.0 + half_size, center.1 + half_size);
+        let (min, max) = pixel_bounds(min, max, area)?;
+
+        Some(Primitive {
+            vertices: [vertex; 3],
+            data,
+            shape: Shape::Point { center, half_size },
+            front_facing: true,
+            min,
+            max,
+        })
+    }
+
+    /// The barycentric coordinates of the center of a pixel, which may lie outside the
+    /// primitive. Lines are parameterized by the projection of the pixel onto the line.
+    fn barycentric_at(&self, x: u32, y: u32) -> [f32; 3] {
+        match self.shape {
+            Shape::Triangle { edges, area, .. } => {
+                let inv_area = 1.0 / area as f32;
+                edges.map(|edge| edge.at(x, y) as f32 * inv_area)
+            }
+            Shape::Line { start, end } => {
+                let t = line_parameter(start, end, x, y);
+                [1.0 - t, t, 0.0]
+            }
+            Shape::Point { .. } => [1.0, 0.0, 0.0],
+        }
+    }
+
+    /// Get the weights used to interpolate the vertex data at a point with the given barycentric
+    /// coordinates, correcting for perspective.
+    #[inline(always)]
+    fn interpolation(&self, barycentric: [f32; 3]) -> [f32; 3] {
+        let vertices = &self.vertices;
+        let perspective = tri_lerp(&[vertices[0].w, vertices[1].w, vertices[2].w], barycentric);
+        let inv_perspective = 1.0 / perspective;
+        [
+            barycentric[0] * vertices[0].w * inv_perspective,
+            barycentric[1] * vertices[1].w * inv_perspective,
+            barycentric[2] * vertices[2].w * inv_perspective,
+        ]
+    }
+
+    /// The smallest and largest depth of the primitive's vertices.
+    fn depth_range(&self) -> (f32, f32) {
+        let depths = self.vertices.map(|vertex| vertex.z);
+        let near = depths[0].min(depths[1]).min(depths[2]);
+        let far = depths[0].max(depths[1]).max(depths[2]);
+        (near, far)
+    }
+
+    /// The depth of the primitive at a point with the given barycentric coordinates.
+    #[inline(always)]
+    fn depth(&self, barycentric: [f32; 3]) -> f32 {
+        let vertices = &self.vertices;
+        tri_lerp(&[vertices[0].z, vertices[1].z, vertices[2].z], barycentric)
+    }
+}
+
+impl<F: Interpolate> Primitive<F> {
+    /// Whether the primitive is drawn as points, either by [`Rasterizer::draw_points`] or in
+    /// [`PolygonMode::Point`]. The vertex data of points is not interpolated.
+    pub fn is_point(&self) -> bool {
+        match self.shape {
+            Shape::Point { .. } => true,
+            Shape::Triangle { outline, .. } => {
+                matches!(
+                    outline,
+                    Some(Outline {
+                        half_size: Some(_),
+                        ..
+                    })
+                )
+            }
+            Shape::Line { .. } => false,
+        }
+    }
+
+    /// Interpolate the vertex data at the center of a pixel, which may lie outside the primitive.
+    pub fn interpolate_at(&self, x: u32, y: u32) -> F {
+        let barycentric = self.barycentric_at(x, y);
+        F::tri_lerp(&self.data, self.interpolation(barycentric))
+    }
+}
+
+impl Edge {
+    /// The edge going from `a` to `b`.
+    fn new(a: (i64, i64), b: (i64, i64)) -> Edge {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+
+        // With y pointing down and the edge functions positive on the inside, top edges are
+        // horizontal and go right, and left edges go up.
+        let is_top = dy == 0 && dx > 0;
+        let is_left = dy < 0;
+
+        Edge {
+            origin: orient_2d(a, b, (HALF_PIXEL, HALF_PIXEL)),
+            step_x: -dy * PIXEL,
+            step_y: dx * PIXEL,
+            bias: if is_top || is_left { 0 } else { 1 },
+        }
+    }
+
+    #[inline(always)]
+    fn at(&self, x: u32, y: u32) -> i64 {
+        self.origin + x as i64 * self.step_x + y as i64 * self.step_y
+    }
+
+    /// Change in value when moving from the center of a pixel to a sample position, given in
+    /// sixteenths of a pixel.
+    fn sample_offset(&self, (x, y): (i64, i64)) -> i64 {
+        (x * self.step_x + y * self.step_y) / 16
+    }
+}
+
+impl Tile {
+    /// Allocate storage for the tile, and copy in the existing contents of the framebuffer
+    /// needed to draw the given shaders with the given rasterizer state.
+    pub fn load<S: ShaderModule, P: RenderTarget, E>(
+        &mut self,
+        pixels: &P,
+        depth_buffer: Option<&DepthBuffer>,
+        rasterizer: &Rasterizer<E>,
+    ) {
+        self.load_depth(depth_buffer, rasterizer);
+        self.shade_fragments = true;
+
+        let pixel_count = self.size.width as usize * self.size.height as usize;
+        self.colors = vec![vec![None; pixel_count]; S::Output::COUNT];
+        self.blend = rasterizer.blend;
+        self.color_write = rasterizer.color_write;
+        if self.reads_destination() {
+            self.destination = (0..S::Output::COUNT)
+                .map(|attachment| {
+                    let colors = self
+                        .pixels()
+                        .map(|pixel| pixels.get_color(attachment, pixel));
+                    colors.collect()
+                })
+                .collect();
+        }
+    }
+
+    /// Like [`load`](Tile::load), but only copies the depth and stencil values, for drawing
+    /// without running the fragment shader.
+    pub fn load_depth<E>(
+        &mut self,
+        depth_buffer: Option<&DepthBuffer>,
+        rasterizer: &Rasterizer<E>,
+    ) {
+        self.samples = SampleCount::One;
+        self.colors = Vec::new();
+        self.destination = Vec::new();
+        self.blend = None;
+        self.color_write = ColorWriteMask::ALL;
+        self.shade_fragments = false;
+        self.depths = depth_buffer
+            .map(|depth_buffer| self.pixels().map(|pixel| depth_buffer.get(pixel)).collect());
+        self.stencil = match (rasterizer.stencil, depth_buffer) {
+            (Some(state), Some(depth_buffer)) if depth_buffer.has_stencil() => {
+                let values = self.pixels().map(|pixel| depth_buffer.get_stencil(pixel));
+                Some((state, values.collect()))
+            }
+            _ => None,
+        };
+        self.reset_depth_bounds();
+    }
+
+    /// Like [`load`](Tile::load), but copies every sample of a multisampled framebuffer.
+    pub fn load_multisampled<E>(&mut self, target: &MultisampleBuffer, rasterizer: &Rasterizer<E>) {
+        let samples = target.samples();
+        let sample_count = self.size.width as usize * self.size.height as usize * samples.count();
+        self.samples = samples;
+        self.colors = vec![vec![None; sample_count]];
+        self.blend = rasterizer.blend;
+        self.color_write = rasterizer.color_write;
+
+        let mut destination = Vec::with_capacity(sample_count);
+        let mut depths = Vec::with_capacity(sample_count);
+        for pixel in self.pixels() {
+            let start = target.index(pixel);
+            let end = start + samples.count();
+            if self.reads_destination() {
+                destination.extend_from_slice(&target.colors[start..end]);
+            }
+            depths.extend_from_slice(&target.depths[start..end]);
+        }
+        self.destination = vec![destination];
+        self.depths = Some(depths);
+        self.stencil = None;
+        self.shade_fragments = true;
+        self.reset_depth_bounds();
+    }
+
+    /// Write the fragments in the tile to the framebuffer.
+    pub fn store<P: RenderTarget>(&self, pixels: &mut P, depth_buffer: Option<&mut DepthBuffer>) {
+        for (attachment, colors) in self.colors.iter().enumerate() {
+            for (index, color) in colors.iter().enumerate() {
+                if let Some(color) = color {
+                    pixels.set_color(attachment, self.pixel(index), *color);
+                }
+            }
+        }
+        self.store_depth(depth_buffer);
+    }
+
+    /// Write the depth and stencil values in the tile to the depth buffer.
+    pub fn store_depth(&self, depth_buffer: Option<&mut DepthBuffer>) {
+        if let (Some(depth_buffer), Some(depths)) = (depth_buffer, &self.depths) {
+            for (index, depth) in depths.iter().enumerate() {
+                depth_buffer.set(self.pixel(index), *depth);
+            }
+            if let Some((_, values)) = &self.stencil {
+                for (index, value) in values.iter().enumerate() {
+                    depth_buffer.set_stencil(self.pixel(index), *value);
+                }
+            }
+        }
+    }
+
+    /// Write the samples in the tile to a multisampled framebuffer.
+    pub fn store_multisampled(&self, target: &mut MultisampleBuffer) {
+        let count = self.samples.count();
+        for (index, color) in self.colors[0].iter().enumerate() {
+            if let Some(color) = color {
+                let sample = target.index(self.pixel(index / count)) + index % count;
+                target.colors[sample] = *color;
+            }
+        }
+
+        if let Some(depths) = &self.depths {
+            for (index, depths) in depths.chunks_exact(count).enumerate() {
+                let start = target.index(self.pixel(index));
+                target.depths[start..start + count].copy_from_slice(depths);
+            }
+        }
+    }
+
+    /// All pixels in the tile, in the order they are stored.
+    fn pixels(&self) -> impl Iterator<Item = PixelCoord> {
+        let min = self.min;
+        let size = self.size;
+        (min.y..min.y + size.height)
+            .flat_map(move |y| (min.x..min.x + size.width).map(move |x| PixelCoord { x, y }))
+    }
+
+    fn pixel(&self, index: usize) -> PixelCoord {
+        PixelCoord {
+            x: self.min.x + index as u32 % self.size.width,
+            y: self.min.y + index as u32 / self.size.width,
+        }
+    }
+
+    /// The index of the first sample of a pixel in the tile.
+    fn first_sample(&self, x: u32, y: u32) -> usize {
+        (x - self.min.x + (y - self.min.y) * self.size.width) as usize * self.samples.count()
+    }
+
+    fn reset_depth_bounds(&mut self) {
+        let blocks_x = self.size.width.div_ceil(DEPTH_BLOCK_SIZE);
+        let blocks_y = self.size.height.div_ceil(DEPTH_BLOCK_SIZE);
+        self.depth_bounds = match (&self.depths, &self.stencil) {
+            (Some(_), None) => vec![None; blocks_x as usize * blocks_y as usize],
+            _ => Vec::new(),
+        };
+    }
+
+    /// The index of the hierarchical depth buffer block containing a pixel.
+    fn depth_block(&self, x: u32, y: u32) -> usize {
+        let blocks_x = self.size.width.div_ceil(DEPTH_BLOCK_SIZE);
+        let block_x = (x - self.min.x) / DEPTH_BLOCK_SIZE;
+        let block_y = (y - self.min.y) / DEPTH_BLOCK_SIZE;
+        (block_x + block_y * blocks_x) as usize
+    }
+
+    /// Whether a primitive can be skipped in the block containing a pixel before running the
+    /// fragment shader, which is only known if the shader does not replace the depth.
+    #[inline(always)]
+    fn hidden<S: ShaderModule>(&mut self, x: u32, y: u32, depth_range: (f32, f32)) -> bool {
+        !<S::Output as FragmentOutput>::WRITES_DEPTH
+            && self.occluded(x, y, depth_range, S::DEPTH_COMPARE)
+    }
+
+    /// Whether a primitive with depths in the given range fails the depth test everywhere in the
+    /// block containing a pixel, according to the hierarchical depth buffer. This is
+    /// conservative, and may return `false` even if the primitive is hidden.
+    fn occluded(
+        &mut self,
+        x: u32,
+        y: u32,
+        (near, far): (f32, f32),
+        compare: CompareFunction,
+    ) -> bool {
+        let block = self.depth_block(x, y);
+        let (min, max) = match self.depth_bounds.get(block) {
+            None => return false,
+            Some(Some(bounds)) => *bounds,
+            Some(None) => {
+                let bounds = self.compute_depth_bounds(block);
+                self.depth_bounds[block] = Some(bounds);
+                bounds
+            }
+        };
+
+        match compare {
+            CompareFunction::Never => true,
+            CompareFunction::Less => near >= max,
+            CompareFunction::LessEqual => near > max,
+            CompareFunction::Greater => far <= min,
+            CompareFunction::GreaterEqual => far < min,
+            CompareFunction::Equal => near > max || far < min,
+            CompareFunction::NotEqual | CompareFunction::Always => false,
+        }
+    }
+
+    /// The smallest and largest depth of the samples in a block.
+    fn compute_depth_bounds(&self, block: usize) -> (f32, f32) {
+        let depths = match &self.depths {
+            Some(depths) => depths,
+            None => return (0.0, 1.0),
+        };
+
+        let blocks_x = self.size.width.div_ceil(DEPTH_BLOCK_SIZE);
+        let min_x = self.min.x + block as u32 % blocks_x * DEPTH_BLOCK_SIZE;
+        let min_y = self.min.y + block as u32 / blocks_x * DEPTH_BLOCK_SIZE;
+        let max_x = (min_x + DEPTH_BLOCK_SIZE).min(self.min.x + self.size.width);
+        let max_y = (min_y + DEPTH_BLOCK_SIZE).min(self.min.y + self.size.height);
+
+        let count = self.samples.count();
+        let mut bounds = (f32::INFINITY, f32::NEG_INFINITY);
+        for y in min_y..max_y {
+            let start = self.first_sample(min_x, y);
+            let end = start + (max_x - min_x) as usize * count;
+            for &depth in &depths[start..end] {
+                bounds = (bounds.0.min(depth), bounds.1.max(depth));
+            }
+        }
+        bounds
+    }
+
+    /// The pixels covered by both the tile and the bounding box of a primitive.
+    fn bounds<F>(&self, primitive: &Primitive<F>) -> (PixelCoord, PixelCoord) {
+        let min = PixelCoord {
+            x: primitive.min.x.max(self.min.x),
+            y: primitive.min.y.max(self.min.y),
+        };
+        let max = PixelCoord {
+            x: primitive.max.x.min(self.min.x + self.size.width),
+            y: primitive.max.y.min(self.min.y + self.size.height),
+        };
+        (min, max)
+    }
+
+    pub fn rasterize<S: ShaderModule>(
+        &mut self,
+        shaders: &S,
+        primitives: &[Primitive<S::FragmentInput>],
+    ) {
+        for index in 0..self.primitives.len() {
+            let primitive = &primitives[self.primitives[index] as usize];
+            match primitive.shape {
+                Shape::Triangle {
+                    edges,
+                    area,
+                    outline: None,
+                } => self.rasterize_triangle(shaders, primitive, edges, area),
+                Shape::Triangle {
+                    outline: Some(outline),
+                    ..
+                } => self.rasterize_outline(shaders, primitive, outline),
+                Shape::Line { start, end } => {
+                    self.rasterize_line(shaders, primitive, start, end, [0, 1])
+                }
+                Shape::Point { center, half_size } => {
+                    self.rasterize_point(shaders, primitive, center, half_size, 0)
+                }
+            }
+        }
+    }
+
+    fn rasterize_triangle<S: ShaderModule>(
+        &mut self,
+        shaders: &S,
+        triangle: &Primitive<S::FragmentInput>,
+        edges: [Edge; 3],
+        area: i64,
+    ) {
+        let (min, max) = self.bounds(triangle);
+        let inv_area = 1.0 / area as f32;
+        let depth_range = triangle.depth_range();
+
+        let pattern = self.samples.pattern();
+        let mut offsets = [[0; 3]; MAX_SAMPLES];
+        for (offset, sample) in offsets.iter_mut().zip(pattern) {
+            *offset = edges.map(|edge| edge.sample_offset(*sample));
+        }
+        let offsets = &offsets[..pattern.len()];
+
+        // walk the triangle one block at a time, skipping blocks where it is hidden
+        let block_start = |value: u32| value - value % DEPTH_BLOCK_SIZE;
+        for block_y in (block_start(min.y)..max.y).step_by(DEPTH_BLOCK_SIZE as usize) {
+            for block_x in (block_start(min.x)..max.x).step_by(DEPTH_BLOCK_SIZE as usize) {
+                if self.hidden::<S>(block_x, block_y, depth_range) {
+                    continue;
+                }
+
+                let (start_x, end_x) = (block_x.max(min.x), max.x.min(block_x + DEPTH_BLOCK_SIZE));
+                let (start_y, end_y) = (block_y.max(min.y), max.y.min(block_y + DEPTH_BLOCK_SIZE));
+                for y in start_y..end_y {
+                    let mut values = edges.map(|edge| edge.at(start_x, y));
+
+                    for x in start_x..end_x {
+                        let center = values;
+
+                        values[0] += edges[0].step_x;
+                        values[1] += edges[1].step_x;
+                        values[2] += edges[2].step_x;
+
+                        // bitmask of the samples that are covered
+                        let mut coverage = 0u32;
+                        let mut depths = [0.0; MAX_SAMPLES];
+                        for (sample, offset) in offsets.iter().enumerate() {
+                            let values = [
+                                center[0] + offset[0],
+                                center[1] + offset[1],
+                                center[2] + offset[2],
+                            ];
+                            let covered = values[0] >= edges[0].bias
+                                && values[1] >= edges[1].bias
+                                && values[2] >= edges[2].bias;
+                            if covered {
+                                let barycentric = values.map(|value| value as f32 * inv_area);
+                                coverage |= 1 << sample;
+                                depths[sample] = triangle.depth(barycentric);
+                            }
+                        }
+
+                        // the fragment shader runs once per pixel, at the center of the pixel
+                        let barycentric = center.map(|value| value as f32 * inv_area);
+                        let pixel = PixelCoord { x, y };
+                        self.shade(shaders, triangle, pixel, barycentric, coverage, &depths);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw the edges or vertices on the boundary of a triangle.
+    fn rasterize_outline<S: ShaderModule>(
+        &mut self,
+        shaders: &S,
+        triangle: &Primitive<S::FragmentInput>,
+        outline: Outline,
+    ) {
+        for start in 0..3 {
+            if !outline.boundary[start] {
+                continue;
+            }
+            let end = (start + 1) % 3;
+            let (point, next) = (outline.points[start], outline.points[end]);
+            match outline.half_size {
+                Some(half_size) => self.rasterize_point(shaders, triangle, point, half_size, start),
+                None => self.rasterize_line(shaders, triangle, point, next, [start, end]),
+            }
+        }
+    }
+
+    /// Lines are rasterized with a DDA along their major axis: every column (or row, for steep
+    /// lines) whose center lies between the end points covers the pixel the line crosses it in.
+    /// The last pixel is left out, so that connected lines do not cover their shared end point
+    /// twice.
+    ///
+    /// The line is drawn between two of the primitive's vertices, given by their indices.
+    fn rasterize_line<S: ShaderModule>(
+        &mut self,
+        shaders: &S,
+        line: &Primitive<S::FragmentInput>,
+        start: (i64, i64),
+        end: (i64, i64),
+        vertices: [usize; 2],
+    ) {
+        if start == end {
+            return;
+        }
+        let (min, max) = self.bounds(line);
+        let endpoints = (start, end);
+
+        // swap the axes of steep lines, so that the first coordinate is along the major axis
+        let x_major = (end.0 - start.0).abs() >= (end.1 - start.1).abs();
+        let swap = |(a, b): (i64, i64)| if x_major { (a, b) } else { (b, a) };
+        let (start, end) = (swap(start), swap(end));
+        let (min, max) = (
+            swap((min.x as i64, min.y as i64)),
+            swap((max.x as i64, max.y as i64)),
+        );
+
+        let ceil_pixel = |value: i64| -(-value).div_euclid(PIXEL);
+        let columns = if start.0 < end.0 {
+            ceil_pixel(start.0 - HALF_PIXEL)..ceil_pixel(end.0 - HALF_PIXEL)
+        } else {
+            (end.0 - HALF_PIXEL).div_euclid(PIXEL) + 1..(start.0 - HALF_PIXEL).div_euclid(PIXEL) + 1
+        };
+
+        let (major, minor) = (end.0 - start.0, end.1 - start.1);
+        let all_samples = (1u32 << self.samples.count()) - 1;
+        for column in columns.start.max(min.0)..columns.end.min(max.0) {
+            // where the line crosses the center of the column, the sign of the major axis is
+            // flipped so that the division rounds down
+            let offset = (column * PIXEL + HALF_PIXEL - start.0) * minor;
+            let crossing = start.1 + (offset * major.signum()).div_euclid(major.abs());
+            let row = crossing.div_euclid(PIXEL);
+            if row < min.1 || row >= max.1 {
+                continue;
+            }
+
+            let (x, y) = swap((column, row));
+            let (x, y) = (x as u32, y as u32);
+            let t = line_parameter(endpoints.0, endpoints.1, x, y).clamp(0.0, 1.0);
+            let mut barycentric = [0.0; 3];
+            barycentric[vertices[0]] = 1.0 - t;
+            barycentric[vertices[1]] = t;
+
+            let depth = line.depth(barycentric);
+            if self.hidden::<S>(x, y, (depth, depth)) {
+                continue;
+            }
+
+            let depths = [depth; MAX_SAMPLES];
+            let pixel = PixelCoord { x, y };
+            self.shade(shaders, line, pixel, barycentric, all_samples, &depths);
+        }
+    }
+
+    /// Points cover the samples within a square centered on one of the primitive's vertices.
+    fn rasterize_point<S: ShaderModule>(
+        &mut self,
+        shaders: &S,
+        point: &Primitive<S::FragmentInput>,
+        center: (i64, i64),
+        half_size: i64,
+        vertex: usize,
+    ) {
+        let min_pixel = |center: i64| (center - half_size).div_euclid(PIXEL).max(0) as u32;
+        let max_pixel = |center: i64| ((center + half_size).div_euclid(PIXEL) + 1).max(0) as u32;
+        let (min, max) = self.bounds(point);
+        let min = PixelCoord {
+            x: min.x.max(min_pixel(center.0)),
+            y: min.y.max(min_pixel(center.1)),
+        };
+        let max = PixelCoord {
+            x: max.x.min(max_pixel(center.0)),
+            y: max.y.min(max_pixel(center.1)),
+        };
+
+        let mut barycentric = [0.0; 3];
+        barycentric[vertex] = 1.0;
+        let depth = point.vertices[vertex].z;
+        let depths = [depth; MAX_SAMPLES];
+        let inside = |position: i64, center: i64| {
+            center - half_size <= position && position < center + half_size
+        };
+
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                if self.hidden::<S>(x, y, (depth, depth)) {
+                    continue;
+                }
+
+                let mut coverage = 0u32;
+                for (sample, (offset_x, offset_y)) in self.samples.pattern().iter().enumerate() {
+                    let sample_x = x as i64 * PIXEL + HALF_PIXEL + offset_x * PIXEL / 16;
+                    let sample_y = y as i64 * PIXEL + HALF_PIXEL + offset_y * PIXEL / 16;
+                    if inside(sample_x, center.0) && inside(sample_y, center.1) {
+                        coverage |= 1 << sample;
+                    }
+                }
+
+                let pixel = PixelCoord { x, y };
+                self.shade(shaders, point, pixel, barycentric, coverage, &depths);
+            }
+        }
+    }
+
+    /// Run the stencil test, then test the depth of a sample against the depth range and the
+    /// depth buffer. Samples failing either test update the stencil buffer right away, while
+    /// samples passing both are updated when the fragment is written.
+    #[inline(always)]
+    fn sample_test<S: ShaderModule>(
+        &mut self,
+        sample: usize,
+        depth: f32,
+        front_facing: bool,
+    ) -> bool {
+        if !(0.0..=1.0).contains(&depth) {
+            return false;
+        }
+
+        let depth_passed = match &self.depths {
+            Some(stored) => S::DEPTH_COMPARE.test(depth, stored[sample]),
+            None => true,
+        };
+
+        if let Some((state, values)) = &mut self.stencil {
+            let face = state.face(front_facing);
+            let stored = &mut values[sample];
+            if !state.test(face, *stored) {
+                *stored = state.update(face.fail, *stored);
+                return false;
+            }
+            if !depth_passed {
+                *stored = state.update(face.depth_fail, *stored);
+            }
+        }
+
+        depth_passed
+    }
+
+    /// Run the stencil and depth tests for the covered samples, returning the mask of samples that
+    /// passed.
+    #[inline(always)]
+    fn test_samples<S: ShaderModule>(
+        &mut self,
+        first_sample: usize,
+        coverage: u32,
+        depths: &[f32; MAX_SAMPLES],
+        front_facing: bool,
+    ) -> u32 {
+        let mut passed = 0;
+        for (sample, depth) in depths.iter().enumerate().take(self.samples.count()) {
+            if coverage & (1 << sample) != 0
+                && self.sample_test::<S>(first_sample + sample, *depth, front_facing)
+            {
+                passed |= 1 << sample;
+            }
+        }
+        passed
+    }
+
+    /// Run the fragment shader for a pixel, and write the fragment to the samples in the coverage
+    /// mask that pass the stencil and depth tests. The tests run before the fragment shader, so
+    /// that hidden fragments are never shaded, unless the output of the shader can discard
+    /// fragments or replace their depth. When only drawing depth the fragment shader is skipped,
+    /// unless it discards fragments or computes their depth.
+    fn shade<S: ShaderModule>(
+        &mut self,
+        shaders: &S,
+        primitive: &Primitive<S::FragmentInput>,
+        pixel: PixelCoord,
+        barycentric: [f32; 3],
+        coverage: u32,
+        depths: &[f32; MAX_SAMPLES],
+    ) {
+        let first_sample = self.first_sample(pixel.x, pixel.y);
+        let writes_depth = <S::Output as FragmentOutput>::WRITES_DEPTH;
+        let late_tests = <S::Output as FragmentOutput>::DISCARDS || writes_depth;
+        let shade_fragments = self.shade_fragments || late_tests;
+        let mut coverage = coverage;
+        if !late_tests {
+            coverage =
+                self.test_samples::<S>(first_sample, coverage, depths, primitive.front_facing);
+        }
+        if coverage == 0 {
+            return;
+        }
+
+        let output = if shade_fragments {
+            let interpolation = primitive.interpolation(barycentric);
+            let frag_data = S::FragmentInput::tri_lerp(&primitive.data, interpolation);
+            let fragment = Fragment {
+                input: &frag_data,
+                primitive,
+                pixel,
+                depth: primitive.depth(barycentric).clamp(0.0, 1.0),
+            };
+            let output = shaders.fragment_shader(&fragment);
+            if output.discarded() {
+                return;
+            }
+            Some(output)
+        } else {
+            None
+        };
+
+        let shader_depths;
+        let depths = match output.as_ref().and_then(FragmentOutput::depth) {
+            Some(depth) if writes_depth => {
+                shader_depths = [depth.clamp(0.0, 1.0); MAX_SAMPLES];
+                &shader_depths
+            }
+            _ => depths,
+        };
+
+        if late_tests {
+            coverage =
+                self.test_samples::<S>(first_sample, coverage, depths, primitive.front_facing);
+        }
+
+        let block = self.depth_block(pixel.x, pixel.y);
+        for (sample, depth) in depths.iter().enumerate().take(self.samples.count()) {
+            if coverage & (1 << sample) == 0 {
+                continue;
+            }
+            let index = first_sample + sample;
+
+            if S::DEPTH_WRITE {
+                if let Some(depths) = &mut self.depths {
+                    depths[index] = *depth;
+                }
+                if let Some(bounds) = self.depth_bounds.get_mut(block) {
+                    *bounds = None;
+                }
+            }
+            if let Some((state, values)) = &mut self.stencil {
+                let face = state.face(primitive.front_facing);
+                values[index] = state.update(face.pass, values[index]);
+            }
+
+            let output = match &output {
+                Some(output) if self.color_write != ColorWriteMask::NONE => output,
+                _ => continue,
+            };
+            let reads_destination = self.reads_destination();
+            let (blend, color_write) = (&self.blend, self.color_write);
+            let destination = &self.destination;
+            for (attachment, colors) in self.colors.iter_mut().enumerate() {
+                let color = output.color(attachment);
+                if !reads_destination {
+                    colors[index] = Some(color);
+                    continue;
+                }
+
+                let existing = colors[index].unwrap_or_else(|| destination[attachment][index]);
+                let color = match blend {
+                    Some(blend) => blend.blend(color, existing),
+                    None => color,
+                };
+                colors[index] = Some(color_write.apply(color, existing));
+            }
+        }
+    }
+
+    /// Whether fragments are combined with the colors already in the framebuffer.
+    #[inline(always)]
+    fn reads_destination(&self) -> bool {
+        self.blend.is_some()
+            || (self.color_write != ColorWriteMask::ALL && self.color_write != ColorWriteMask::NONE)
+    }
+}
+
+/// Perform the perspective divide, storing `1 / w` in the `w` component for perspective correct
+/// interpolation.
+fn unproject(vector: Vector4) -> Vector4 {
+    let inv_w = 1.0 / vector.w;
+    Vector4 {
+        x: vector.x * inv_w,
+        y: vector.y * inv_w,
+        z: vector.z * inv_w,
+        w: inv_w,
+    }
+}
+
+/// The pixels that may contain samples within a bounding box given in fixed-point screen
+/// coordinates, clamped to the render area. `None` if the bounding box lies outside the render
+/// area.
+fn pixel_bounds(
+    min: (i64, i64),
+    max: (i64, i64),
+    area: &RenderArea,
+) -> Option<(PixelCoord, PixelCoord)> {
+    let clamp_x = |pixel: i64| pixel.clamp(area.min.x as i64, area.max.x as i64) as u32;
+    let clamp_y = |pixel: i64| pixel.clamp(area.min.y as i64, area.max.y as i64) as u32;
+    // every sample position lies within its pixel, so this covers all pixels with samples
+    // inside the bounding box
+    let min_pixel = PixelCoord {
+        x: clamp_x(min.0.div_euclid(PIXEL)),
+        y: clamp_y(min.1.div_euclid(PIXEL)),
+    };
+    let max_pixel = PixelCoord {
+        x: clamp_x(max.0.div_euclid(PIXEL) + 1),
+        y: clamp_y(max.1.div_euclid(PIXEL) + 1),
+    };
+
+    if min_pixel.x >= max_pixel.x || min_pixel.y >= max_pixel.y {
+        return None;
+    }
+    Some((min_pixel, max_pixel))
+}
+
+/// Half the width of a point in fixed-point units, `None` if the point is too small to cover
+/// any samples.
+fn point_half_size(point_size: f32) -> Option<i64> {
+    let half_size = (0.5 * point_size * PIXEL as f32).round() as i64;
+    Some(half_size).filter(|&half_size| half_size > 0)
+}
+
+/// The position of the projection of the center of a pixel onto a line, where `0` is the start
+/// and `1` the end of the line.
+fn line_parameter(start: (i64, i64), end: (i64, i64), x: u32, y: u32) -> f32 {
+    let center = (x as i64 * PIXEL + HALF_PIXEL, y as i64 * PIXEL + HALF_PIXEL);
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let projection = (center.0 - start.0) * dx + (center.1 - start.1) * dy;
+    projection as f32 / (dx * dx + dy * dy) as f32
+}
+
+/// Twice the signed area of the triangle `abc`. Positive if the points are in clockwise order on
+/// the screen.
+#[inline(always)]
+fn orient_2d(a: (i64, i64), b: (i64, i64), c: (i64, i64)) -> i64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn triangle_winding_order([a, b, c]: [Vector2; 3]) -> WindingOrder {
+    let side_area = |current: Vector2, next: Vector2| (next.x - current.x) * (next.y + current.y);
+    let areas = [side_area(a, b), side_area(b, c), side_area(c, a)];
+    let total_area = areas.iter().sum::<f32>();
+    if total_area > 0.0 {
+        WindingOrder::Clockwise
+    } else {
+        WindingOrder::CounterClockwise
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn winding_order() {
+        let triangle = [
+            Vector2::new(1.0, 2.0),
+            Vector2::new(2.0, 2.0),
+            Vector2::new(1.0, 1.0),
+        ];
+
+        assert_eq!(triangle_winding_order(triangle), WindingOrder::Clockwise);
+    }
+
+    #[test]
+    fn triangles_are_binned_into_covered_tiles() {
+        let size = Dimensions::from([3 * TILE_SIZE, 2 * TILE_SIZE - 10]);
+        let triangle = |min: [u32; 2], max: [u32; 2]| Primitive {
+            vertices: [Vector4::ORIGIN; 3],
+            data: [(); 3],
+            shape: Shape::Triangle {
+                edges: [Edge::new((0, 0), (0, 0)); 3],
+                area: 0,
+                outline: None,
+            },
+            front_facing: true,
+            min: PixelCoord {
+                x: min[0],
+                y: min[1],
+            },
+            max: PixelCoord {
+                x: max[0],
+                y: max[1],
+            },
+        };
+        let triangles = [
+            triangle([0, 0], [10, 10]),
+            triangle([TILE_SIZE - 1, 0], [TILE_SIZE + 1, TILE_SIZE + 1]),
+        ];
+
+        let tiles = bin_primitives(size, &triangles);
+        let bins = tiles
+            .iter()
+            .map(|tile| (tile.min.x, tile.min.y, tile.primitives.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            bins,
+            vec![
+                (0, 0, vec![0, 1]),
+                (TILE_SIZE, 0, vec![1]),
+                (0, TILE_SIZE, vec![1]),
+                (TILE_SIZE, TILE_SIZE, vec![1]),
+            ]
+        );
+        assert_eq!(tiles[3].size, Dimensions::from([TILE_SIZE, TILE_SIZE - 10]));
+    }
+
+    #[test]
+    fn hierarchical_depth_rejects_hidden_blocks() {
+        let size = Dimensions::from([16, 12]);
+        let mut depth_buffer = DepthBuffer::new(size);
+        for y in 0..size.height {
+            for x in 0..8 {
+                depth_buffer.set(PixelCoord { x, y }, 0.25);
+            }
+        }
+        depth_buffer.set(PixelCoord { x: 3, y: 11 }, 0.75);
+
+        let primitive = Primitive {
+            vertices: [Vector4::ORIGIN; 3],
+            data: [(); 3],
+            shape: Shape::Point {
+                center: (0, 0),
+                half_size: 0,
+            },
+            front_facing: true,
+            min: PixelCoord { x: 0, y: 0 },
+            max: PixelCoord { x: 1, y: 1 },
+        };
+        let mut tile = bin_primitives(size, &[primitive]).remove(0);
+        tile.load_depth(Some(&depth_buffer), &Rasterizer::default());
+
+        let behind = (0.5, 0.6);
+        assert!(tile.occluded(0, 0, behind, CompareFunction::Less));
+        assert!(!tile.occluded(8, 0, behind, CompareFunction::Less));
+        assert!(!tile.occluded(0, 0, (0.1, 0.6), CompareFunction::Less));
+        assert!(!tile.occluded(0, 0, behind, CompareFunction::Greater));
+        assert!(tile.occluded(0, 0, (0.1, 0.2), CompareFunction::GreaterEqual));
+        // the lower blocks are only partially inside the tile
+        assert!(!tile.occluded(7, 8, behind, CompareFunction::Less));
+        assert!(tile.occluded(7, 8, (0.75, 1.0), CompareFunction::Less));
+    }
+
+    #[test]
+    fn top_left_fill_rule() {
+        // a square split along its diagonal, with corners at pixel centers, so that pixels lie
+        // exactly on every edge of both triangles
+        let corner = |x: i64, y: i64| (x * PIXEL + HALF_PIXEL, y * PIXEL + HALF_PIXEL);
+        let [a, b, c, d] = [corner(0, 0), corner(4, 0), corner(4, 4), corner(0, 4)];
+
+        let covers = |[p, q, r]: [(i64, i64); 3], x: u32, y: u32| {
+            let edges = [Edge::new(q, r), Edge::new(r, p), Edge::new(p, q)];
+            edges.iter().all(|edge| edge.at(x, y) >= edge.bias)
+        };
+
+        for y in 0..6 {
+            for x in 0..6 {
+                let coverage = covers([a, b, c], x, y) as u32 + covers([c, d, a], x, y) as u32;
+                let inside = x < 4 && y < 4;
+                assert_eq!(coverage, inside as u32, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn lines_cover_one_pixel_per_step() {
+        let mut pixels = TestBuffer::new(8, 8);
+
+        let vertices = vec![
+            (screen_position(0.5, 0.5), Color::from([0.0, 0.0, 1.0])),
+            (screen_position(7.5, 3.5), Color::WHITE),
+            (screen_position(7.5, 7.5), Color::WHITE),
+        ];
+        let indices = [[0, 1].into(), [1, 2].into()];
+        Rasterizer::default().draw_lines(&mut pixels, None, &FlatShaders, &vertices, &indices);
+
+        // the shared end point is only covered by the second line
+        let expected = [
+            (0, 0),
+            (1, 0),
+            (2, 1),
+            (3, 1),
+            (4, 2),
+            (5, 2),
+            (6, 3),
+            (7, 3),
+            (7, 4),
+            (7, 5),
+            (7, 6),
+        ];
+        let mut covered = pixels.covered();
+        covered.sort_by_key(|&(x, y)| (x, y));
+        assert_eq!(covered, expected);
+
+        // colors are interpolated by projecting the pixel onto the line
+        assert_eq!(
+            pixels.get(PixelCoord { x: 0, y: 0 }),
+            Color::from([0.0, 0.0, 1.0])
+        );
+        let color = pixels.get(PixelCoord { x: 6, y: 3 });
+        assert!((color.r - 51.0 / 58.0).abs() < 1e-5, "{:?}", color);
+    }
+
+    #[test]
+    fn points_cover_a_square() {
+        let mut pixels = TestBuffer::new(8, 8);
+
+        let vertices = vec![
+            (screen_position(2.5, 2.5), Color::WHITE),
+            (screen_position(7.0, 7.0), Color::WHITE),
+            // outside the visible volume
+            (screen_position(9.0, 2.0), Color::WHITE),
+        ];
+        let rasterizer = Rasterizer {
+            point_size: 3.0,
+            ..Rasterizer::default()
+        };
+        rasterizer.draw_points(&mut pixels, None, &FlatShaders, &vertices, &[0, 1, 2]);
+
+        let square =
+            |min: u32| (min..min + 3).flat_map(move |y| (min..min + 3).map(move |x| (x, y)));
+        let expected: Vec<_> = square(1).chain(square(5)).collect();
+        assert_eq!(pixels.covered(), expected);
+    }
+
+    #[test]
+    fn polygon_modes_outline_triangles() {
+        let quad = vec![
+            (screen_position(1.5, 1.5), Color::WHITE),
+            (screen_position(5.5, 1.5), Color::WHITE),
+            (screen_position(5.5, 5.5), Color::WHITE),
+            (screen_position(1.5, 5.5), Color::WHITE),
+        ];
+
+        let render = |polygon_mode, vertices: &Vec<_>, indices: &[Triangle<VertexIndex>]| {
+            let mut pixels = TestBuffer::new(8, 8);
+            let rasterizer = Rasterizer {
+                polygon_mode,
+                ..Rasterizer::default()
+            };
+            rasterizer.draw(&mut pixels, None, &FlatShaders, vertices, indices);
+            pixels.covered()
+        };
+
+        let edges = render(PolygonMode::Line, &quad, &QUAD);
+        assert_eq!(edges.len(), 4 * 4 + 3);
+        assert!(edges.contains(&(3, 3)) && edges.contains(&(3, 1)) && edges.contains(&(1, 3)));
+        assert!(!edges.contains(&(4, 2)) && !edges.contains(&(2, 4)));
+
+        let corners = render(PolygonMode::Point, &quad, &QUAD);
+        assert_eq!(corners, [(1, 1), (5, 1), (1, 5), (5, 5)]);
+
+        // clipping splits the triangle in two, but only the outline of the original triangle is
+        // drawn
+        let clipped = vec![
+            (screen_position(1.5, 1.5), Color::WHITE),
+            (screen_position(12.0, 1.5), Color::WHITE),
+            (screen_position(1.5, 6.5), Color::WHITE),
+        ];
+        let mut lines = TestBuffer::new(8, 8);
+        let line_indices = [[0, 1].into(), [1, 2].into(), [2, 0].into()];
+        Rasterizer::default().draw_lines(&mut lines, None, &FlatShaders, &clipped, &line_indices);
+        let outline = render(PolygonMode::Line, &clipped, &[[0, 1, 2].into()]);
+        assert_eq!(outline, lines.covered());
+    }
+
+    #[test]
+    fn viewport_and_scissor() {
+        let quad = quad(0.5, Color::WHITE);
+        let render = |rasterizer: Rasterizer| {
+            let mut pixels = TestBuffer::new(4, 4);
+            let mut depth = DepthBuffer::new(pixels.size);
+            rasterizer.draw(&mut pixels, Some(&mut depth), &FlatShaders, &quad, &QUAD);
+            (pixels.covered(), depth)
+        };
+
+        // the top right quarter of the framebuffer, with depths in the far half
+        let (covered, depth) = render(Rasterizer {
+            viewport: Some(Viewport {
+                x: 2.0,
+                y: 0.0,
+                width: 2.0,
+                height: 2.0,
+                min_depth: 0.5,
+                max_depth: 1.0,
+            }),
+            ..Rasterizer::default()
+        });
+        assert_eq!(covered, [(2, 0), (3, 0), (2, 1), (3, 1)]);
+        assert_eq!(depth.get(PixelCoord { x: 3, y: 1 }), 0.75);
+
+        let (covered, _) = render(Rasterizer {
+            scissor: Some(Rect {
+                x: 1,
+                y: 2,
+                width: 2,
+                height: 5,
+            }),
+            ..Rasterizer::default()
+        });
+        assert_eq!(covered, [(1, 2), (2, 2), (1, 3), (2, 3)]);
+    }
+
+    struct FragmentCountingShaders {
+        invocations: std::cell::Cell<usize>,
+    }
+
+    impl ShaderModule for FragmentCountingShaders {
+        type VertexInput = (Vector4, Color);
+        type FragmentInput = Color;
+        type Output = Color;
+
+        fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+            *vertex
+        }
+
+        fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> Color {
+            self.invocations.set(self.invocations.get() + 1);
+            *fragment.input()
+        }
+    }
+
+    #[test]
+    fn hidden_fragments_are_not_shaded() {
+        let mut pixels = TestBuffer::new(20, 20);
+        let mut depth = DepthBuffer::new(pixels.size);
+        let shaders = FragmentCountingShaders {
+            invocations: Default::default(),
+        };
+
+        // the near quad covers the right half, so only the left half of the far quad is visible
+        let mut near = quad(0.25, Color::WHITE);
+        near[0].0.x = 0.0;
+        near[3].0.x = 0.0;
+        let far = quad(0.75, Color::WHITE);
+        draw(&mut pixels, Some(&mut depth), &shaders, &near, &QUAD);
+        draw(&mut pixels, Some(&mut depth), &shaders, &far, &QUAD);
+        assert_eq!(shaders.invocations.into_inner(), 200 + 200);
+    }
+
+    /// A quad covering the whole framebuffer, with a transparent left half.
+    fn half_transparent_quad() -> (Vec<(Vector4, Color)>, [Triangle<VertexIndex>; 4]) {
+        let mut left = quad(0.5, Color::from([1.0, 0.0, 0.0, 0.0]));
+        left[1].0.x = 0.0;
+        left[2].0.x = 0.0;
+        let mut right = quad(0.5, Color::from([1.0, 0.0, 0.0, 1.0]));
+        right[0].0.x = 0.0;
+        right[3].0.x = 0.0;
+        let indices = [
+            [0, 1, 2].into(),
+            [2, 3, 0].into(),
+            [4, 5, 6].into(),
+            [6, 7, 4].into(),
+        ];
+        (left.into_iter().chain(right).collect(), indices)
+    }
+
+    #[test]
+    fn discarded_fragments_are_not_written() {
+        let mut pixels = TestBuffer::new(4, 4);
+        let mut depth = DepthBuffer::new(pixels.size);
+        let (vertices, indices) = half_transparent_quad();
+        draw(
+            &mut pixels,
+            Some(&mut depth),
+            &DiscardShaders,
+            &vertices,
+            &indices,
+        );
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let pixel = PixelCoord { x, y };
+                let (expected_color, expected_depth) = if x < 2 {
+                    (Color::BLACK, 1.0)
+                } else {
+                    (Color::from([1.0, 0.0, 0.0, 1.0]), 0.5)
+                };
+                assert_eq!(pixels.get(pixel), expected_color);
+                assert_eq!(depth.get(pixel), expected_depth);
+            }
+        }
+    }
+
+    struct DepthOnlyShaders;
+
+    impl ShaderModule for DepthOnlyShaders {
+        type VertexInput = (Vector4, Color);
+        type FragmentInput = ();
+        type Output = Color;
+
+        fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+            (vertex.0, ())
+        }
+
+        fn fragment_shader(&self, _: &Fragment<Self::FragmentInput>) -> Color {
+            panic!("the fragment shader should not run when drawing depth")
+        }
+    }
+
+    #[test]
+    fn draw_depth_skips_fragment_shader() {
+        let mut depth = DepthBuffer::new(Dimensions::from([4, 4]));
+        let vertices = quad(0.25, Color::WHITE);
+        Rasterizer::default().draw_depth(&mut depth, &DepthOnlyShaders, &vertices, &QUAD[..1]);
+
+        assert_eq!(depth.get(PixelCoord { x: 3, y: 3 }), 0.25);
+        assert_eq!(depth.get(PixelCoord { x: 0, y: 0 }), 1.0);
+    }
+
+    #[test]
+    fn draw_depth_skips_discarded_fragments() {
+        let mut depth = DepthBuffer::new(Dimensions::from([4, 4]));
+        let (vertices, indices) = half_transparent_quad();
+        Rasterizer::default().draw_depth(&mut depth, &DiscardShaders, &vertices, &indices);
+
+        for y in 0..4 {
+            assert_eq!(depth.get(PixelCoord { x: 0, y }), 1.0);
+            assert_eq!(depth.get(PixelCoord { x: 3, y }), 0.5);
+        }
+    }
+}
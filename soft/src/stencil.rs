@@ -0,0 +1,256 @@
+//! Stencil testing, which masks fragments using a value stored for every pixel in the
+//! [`DepthBuffer`](crate::DepthBuffer), and updates the stored value depending on the outcome
+//! of the stencil and depth tests.
+
+use crate::CompareFunction;
+
+/// Configures the stencil test and the operations applied to the stencil buffer. Triangles may
+/// use different settings depending on which way they face.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StencilState {
+    /// Used by front facing triangles, lines and points.
+    pub front: StencilFace,
+    /// Used by back facing triangles.
+    pub back: StencilFace,
+    /// The value compared against the stencil buffer, and written by [`StencilOp::Replace`].
+    pub reference: u8,
+    /// The bits of the reference and stored values that are compared.
+    pub compare_mask: u8,
+    /// The bits of the stored value that the operations are allowed to change.
+    pub write_mask: u8,
+}
+
+/// The stencil test and operations used by primitives facing one way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StencilFace {
+    /// A fragment passes the stencil test if `(reference & compare_mask)` compares successfully
+    /// against `(stored & compare_mask)`.
+    pub compare: CompareFunction,
+    /// Applied when the stencil test fails.
+    pub fail: StencilOp,
+    /// Applied when the stencil test passes, but the depth test fails.
+    pub depth_fail: StencilOp,
+    /// Applied when both the stencil and depth tests pass.
+    pub pass: StencilOp,
+}
+
+/// Computes the new value in the stencil buffer from the stored value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// Write the reference value.
+    Replace,
+    /// Add one, keeping the value at 255 if it would overflow.
+    IncrementClamp,
+    /// Subtract one, keeping the value at 0 if it would underflow.
+    DecrementClamp,
+    /// Add one, wrapping around to 0 on overflow.
+    IncrementWrap,
+    /// Subtract one, wrapping around to 255 on underflow.
+    DecrementWrap,
+    /// Flip all bits.
+    Invert,
+}
+
+impl StencilState {
+    /// Use the same test and operations for both faces, comparing and writing all bits.
+    pub const fn uniform(face: StencilFace, reference: u8) -> StencilState {
+        StencilState {
+            front: face,
+            back: face,
+            reference,
+            compare_mask: u8::MAX,
+            write_mask: u8::MAX,
+        }
+    }
+
+    /// Always pass the stencil test, writing the reference value wherever a fragment is drawn.
+    pub const fn write(reference: u8) -> StencilState {
+        StencilState::uniform(
+            StencilFace {
+                compare: CompareFunction::Always,
+                fail: StencilOp::Keep,
+                depth_fail: StencilOp::Keep,
+                pass: StencilOp::Replace,
+            },
+            reference,
+        )
+    }
+
+    /// Only draw where the stencil buffer equals the reference value, leaving it unchanged.
+    pub const fn equal(reference: u8) -> StencilState {
+        StencilState::uniform(
+            StencilFace {
+                compare: CompareFunction::Equal,
+                fail: StencilOp::Keep,
+                depth_fail: StencilOp::Keep,
+                pass: StencilOp::Keep,
+            },
+            reference,
+        )
+    }
+
+    #[inline(always)]
+    pub(crate) fn face(&self, front_facing: bool) -> &StencilFace {
+        if front_facing {
+            &self.front
+        } else {
+            &self.back
+        }
+    }
+
+    /// Compare the reference value against a value in the stencil buffer.
+    #[inline(always)]
+    pub fn test(&self, face: &StencilFace, stored: u8) -> bool {
+        face.compare.test(
+            self.reference & self.compare_mask,
+            stored & self.compare_mask,
+        )
+    }
+
+    /// Apply an operation to a value in the stencil buffer, only changing the bits in the write
+    /// mask.
+    #[inline(always)]
+    pub fn update(&self, op: StencilOp, stored: u8) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (value & self.write_mask) | (stored & !self.write_mask)
+    }
+}
+
+impl StencilOp {
+    pub fn apply(self, stored: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => stored.saturating_add(1),
+            StencilOp::DecrementClamp => stored.saturating_sub(1),
+            StencilOp::IncrementWrap => stored.wrapping_add(1),
+            StencilOp::DecrementWrap => stored.wrapping_sub(1),
+            StencilOp::Invert => !stored,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::{Color, DepthBuffer, Dimensions, PixelCoord, Rasterizer, Rect};
+
+    #[test]
+    fn operations_clamp_and_wrap() {
+        assert_eq!(StencilOp::IncrementClamp.apply(255, 0), 255);
+        assert_eq!(StencilOp::DecrementClamp.apply(0, 0), 0);
+        assert_eq!(StencilOp::IncrementWrap.apply(255, 0), 0);
+        assert_eq!(StencilOp::DecrementWrap.apply(0, 0), 255);
+        assert_eq!(StencilOp::Invert.apply(0b1010_0000, 0), 0b0101_1111);
+        assert_eq!(StencilOp::Replace.apply(3, 7), 7);
+    }
+
+    #[test]
+    fn masks_limit_compared_and_written_bits() {
+        let state = StencilState {
+            compare_mask: 0x0f,
+            write_mask: 0xf0,
+            ..StencilState::equal(0x12)
+        };
+        assert!(state.test(&state.front, 0x32));
+        assert!(!state.test(&state.front, 0x13));
+        assert_eq!(state.update(StencilOp::Replace, 0x0f), 0x1f);
+        assert_eq!(state.update(StencilOp::Invert, 0x0f), 0xff);
+    }
+
+    #[test]
+    fn stencil_masks_fragments() {
+        let quad = quad(0.5, Color::WHITE);
+        let mut depth = DepthBuffer::with_stencil(Dimensions::from([4, 4]));
+        let render = |rasterizer: Rasterizer, depth: &mut DepthBuffer| {
+            let mut pixels = TestBuffer::new(4, 4);
+            rasterizer.draw(&mut pixels, Some(depth), &FlatShaders, &quad, &QUAD);
+            pixels.covered()
+        };
+
+        // mark the left column
+        render(
+            Rasterizer {
+                scissor: Some(Rect {
+                    x: 0,
+                    y: 0,
+                    width: 1,
+                    height: 4,
+                }),
+                stencil: Some(StencilState::write(1)),
+                ..Rasterizer::default()
+            },
+            &mut depth,
+        );
+
+        let increment_on_depth_fail = Rasterizer {
+            stencil: Some(StencilState::uniform(
+                StencilFace {
+                    compare: CompareFunction::Equal,
+                    fail: StencilOp::Keep,
+                    depth_fail: StencilOp::IncrementClamp,
+                    pass: StencilOp::Keep,
+                },
+                0,
+            )),
+            ..Rasterizer::default()
+        };
+        let covered = render(increment_on_depth_fail, &mut depth);
+        assert_eq!(covered.len(), 12);
+        assert!(covered.iter().all(|&(x, _)| x != 0));
+        assert_eq!(depth.get_stencil(PixelCoord { x: 3, y: 2 }), 0);
+
+        // the quad is now behind itself, so the unmarked pixels are incremented
+        let covered = render(increment_on_depth_fail, &mut depth);
+        assert!(covered.is_empty());
+        assert_eq!(depth.get_stencil(PixelCoord { x: 3, y: 2 }), 1);
+        assert_eq!(depth.get_stencil(PixelCoord { x: 0, y: 2 }), 1);
+
+        depth.clear(1.0);
+        let covered = render(
+            Rasterizer {
+                stencil: Some(StencilState::equal(1)),
+                ..Rasterizer::default()
+            },
+            &mut depth,
+        );
+        assert_eq!(covered.len(), 16);
+    }
+
+    #[test]
+    fn discarded_fragments_do_not_update_stencil() {
+        let mut pixels = TestBuffer::new(4, 4);
+        let mut depth = DepthBuffer::with_stencil(pixels.size);
+        depth.clear(0.25);
+
+        // the quad is hidden, only its opaque right half replaces the stencil value
+        let transparent = quad(0.5, Color::from([1.0, 0.0, 0.0, 0.0]));
+        let mut opaque = quad(0.5, Color::from([1.0, 0.0, 0.0, 1.0]));
+        opaque[0].0.x = 0.0;
+        opaque[3].0.x = 0.0;
+        let rasterizer = Rasterizer {
+            stencil: Some(StencilState::uniform(
+                StencilFace {
+                    compare: CompareFunction::Always,
+                    fail: StencilOp::Keep,
+                    depth_fail: StencilOp::Replace,
+                    pass: StencilOp::Keep,
+                },
+                1,
+            )),
+            ..Rasterizer::default()
+        };
+        for vertices in &[transparent, opaque] {
+            let target = Some(&mut depth);
+            rasterizer.draw(&mut pixels, target, &DiscardShaders, vertices, &QUAD);
+        }
+
+        assert!(pixels.covered().is_empty());
+        assert_eq!(depth.get_stencil(PixelCoord { x: 1, y: 2 }), 0);
+        assert_eq!(depth.get_stencil(PixelCoord { x: 2, y: 2 }), 1);
+    }
+}
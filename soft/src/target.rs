@@ -0,0 +1,332 @@
+//! Render targets with multiple color attachments, written by fragment shaders that output
+//! multiple colors.
+
+use crate::{Color, Dimensions, PixelBuffer, PixelCoord};
+
+/// The colors written by the fragment shader, one for each attachment of the
+/// [`RenderTarget`]. Implemented for [`Color`], tuples of outputs and `Option`s of outputs, and
+/// can be derived for structs whose fields are outputs. A fragment is discarded if any part of its
+/// output is discarded.
+pub trait FragmentOutput {
+    /// Number of colors in the output.
+    const COUNT: usize;
+
+    /// Whether the output can discard fragments. Must be `true` if
+    /// [`discarded`](FragmentOutput::discarded) ever returns `true`, so that the fragment shader is
+    /// run even when only drawing depth. The stencil and depth tests then run after the fragment
+    /// shader, so that discarded fragments never update the stencil buffer. Otherwise the tests
+    /// run first, and the fragment shader only runs for fragments that pass them.
+    const DISCARDS: bool = false;
+
+    /// Whether the output replaces the depth of fragments, such as [`WithDepth`]. Like with
+    /// [`DISCARDS`](FragmentOutput::DISCARDS), the stencil and depth tests then run after the
+    /// fragment shader, and hidden fragments are no longer skipped before shading.
+    const WRITES_DEPTH: bool = false;
+
+    /// The color written to the attachment with the given index.
+    fn color(&self, index: usize) -> Color;
+
+    /// Whether the fragment is discarded, in which case it is written to neither the render target
+    /// nor the depth buffer.
+    #[inline(always)]
+    fn discarded(&self) -> bool {
+        false
+    }
+
+    /// The depth written instead of the interpolated depth of the fragment, if any. Only used if
+    /// [`WRITES_DEPTH`](FragmentOutput::WRITES_DEPTH) is `true`.
+    #[inline(always)]
+    fn depth(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// A fragment output together with the depth of the fragment, which replaces the depth
+/// interpolated from the vertices of the primitive in the depth test and depth buffer. The depth
+/// is clamped to the range `[0, 1]`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WithDepth<T> {
+    pub output: T,
+    pub depth: f32,
+}
+
+/// One or more color attachments of the same size that fragments are written to. Every
+/// [`PixelBuffer`] is a render target with a single attachment, and tuples of pixel buffers
+/// have one attachment per buffer.
+pub trait RenderTarget {
+    /// The size of every attachment.
+    fn target_size(&self) -> Dimensions;
+
+    /// Number of color attachments.
+    fn attachment_count(&self) -> usize;
+
+    /// Get the color of a pixel in an attachment.
+    fn get_color(&self, attachment: usize, pixel: PixelCoord) -> Color;
+
+    /// Set a pixel in an attachment to a specific color.
+    fn set_color(&mut self, attachment: usize, pixel: PixelCoord, color: Color);
+}
+
+impl FragmentOutput for Color {
+    const COUNT: usize = 1;
+
+    #[inline(always)]
+    fn color(&self, _index: usize) -> Color {
+        *self
+    }
+}
+
+impl<T: FragmentOutput> FragmentOutput for Option<T> {
+    const COUNT: usize = T::COUNT;
+    const DISCARDS: bool = true;
+    const WRITES_DEPTH: bool = T::WRITES_DEPTH;
+
+    #[inline(always)]
+    fn color(&self, index: usize) -> Color {
+        self.as_ref()
+            .map_or(Color::TRANSPARENT, |output| output.color(index))
+    }
+
+    #[inline(always)]
+    fn discarded(&self) -> bool {
+        self.as_ref().is_none_or(T::discarded)
+    }
+
+    #[inline(always)]
+    fn depth(&self) -> Option<f32> {
+        self.as_ref().and_then(T::depth)
+    }
+}
+
+impl<T: FragmentOutput> FragmentOutput for WithDepth<T> {
+    const COUNT: usize = T::COUNT;
+    const DISCARDS: bool = T::DISCARDS;
+    const WRITES_DEPTH: bool = true;
+
+    #[inline(always)]
+    fn color(&self, index: usize) -> Color {
+        self.output.color(index)
+    }
+
+    #[inline(always)]
+    fn discarded(&self) -> bool {
+        self.output.discarded()
+    }
+
+    #[inline(always)]
+    fn depth(&self) -> Option<f32> {
+        Some(self.depth)
+    }
+}
+
+impl<P: PixelBuffer> RenderTarget for P {
+    fn target_size(&self) -> Dimensions {
+        self.size()
+    }
+
+    fn attachment_count(&self) -> usize {
+        1
+    }
+
+    fn get_color(&self, _attachment: usize, pixel: PixelCoord) -> Color {
+        self.get(pixel)
+    }
+
+    fn set_color(&mut self, _attachment: usize, pixel: PixelCoord, color: Color) {
+        self.set(pixel, color)
+    }
+}
+
+impl<P: PixelBuffer + ?Sized> PixelBuffer for &mut P {
+    fn size(&self) -> Dimensions {
+        (**self).size()
+    }
+
+    fn get(&self, pixel: PixelCoord) -> Color {
+        (**self).get(pixel)
+    }
+
+    fn set(&mut self, pixel: PixelCoord, color: Color) {
+        (**self).set(pixel, color)
+    }
+}
+
+macro_rules! impl_tuples {
+    ($count:literal; $($index:tt $name:ident),*) => {
+        impl<$($name: FragmentOutput),*> FragmentOutput for ($($name,)*) {
+            const COUNT: usize = 0 $(+ $name::COUNT)*;
+            const DISCARDS: bool = false $(|| $name::DISCARDS)*;
+            const WRITES_DEPTH: bool = false $(|| $name::WRITES_DEPTH)*;
+
+            fn color(&self, mut index: usize) -> Color {
+                $(
+                    if index < $name::COUNT {
+                        return self.$index.color(index);
+                    }
+                    index -= $name::COUNT;
+                )*
+                panic!("fragment output index out of range")
+            }
+
+            fn discarded(&self) -> bool {
+                false $(|| self.$index.discarded())*
+            }
+
+            fn depth(&self) -> Option<f32> {
+                None $(.or_else(|| self.$index.depth()))*
+            }
+        }
+
+        impl<$($name: PixelBuffer),*> RenderTarget for ($($name,)*) {
+            fn target_size(&self) -> Dimensions {
+                let size = self.0.size();
+                $(
+                    assert_eq!(
+                        self.$index.size(),
+                        size,
+                        "render target attachments have different sizes"
+                    );
+                )*
+                size
+            }
+
+            fn attachment_count(&self) -> usize {
+                $count
+            }
+
+            fn get_color(&self, attachment: usize, pixel: PixelCoord) -> Color {
+                match attachment {
+                    $($index => self.$index.get(pixel),)*
+                    _ => panic!("attachment index out of range"),
+                }
+            }
+
+            fn set_color(&mut self, attachment: usize, pixel: PixelCoord, color: Color) {
+                match attachment {
+                    $($index => self.$index.set(pixel, color),)*
+                    _ => panic!("attachment index out of range"),
+                }
+            }
+        }
+    };
+}
+
+impl_tuples!(2; 0 A, 1 B);
+impl_tuples!(3; 0 A, 1 B, 2 C);
+impl_tuples!(4; 0 A, 1 B, 2 C, 3 D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::{draw, DepthBuffer, Fragment, Rasterizer, ShaderModule, Vector4};
+
+    #[test]
+    fn nested_outputs_are_flattened() {
+        let red = Color::from([1.0, 0.0, 0.0]);
+        let green = Color::from([0.0, 1.0, 0.0]);
+        let output = (red, (green, Color::BLACK), Color::WHITE);
+        assert_eq!(<(Color, (Color, Color), Color)>::COUNT, 4);
+        assert_eq!(output.color(0), red);
+        assert_eq!(output.color(1), green);
+        assert_eq!(output.color(2), Color::BLACK);
+        assert_eq!(output.color(3), Color::WHITE);
+        assert_eq!(output.depth(), None);
+    }
+
+    #[test]
+    fn depth_is_taken_from_nested_outputs() {
+        let output = (
+            Color::BLACK,
+            WithDepth {
+                output: (Color::WHITE, Color::BLACK),
+                depth: 0.25,
+            },
+        );
+        assert_eq!(<(Color, WithDepth<(Color, Color)>)>::COUNT, 3);
+        assert_eq!(output.color(1), Color::WHITE);
+        assert_eq!(output.depth(), Some(0.25));
+    }
+
+    struct MultipleOutputShaders;
+
+    impl ShaderModule for MultipleOutputShaders {
+        type VertexInput = (Vector4, Color);
+        type FragmentInput = Color;
+        type Output = (Color, Color);
+
+        fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+            *vertex
+        }
+
+        fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> (Color, Color) {
+            let color = *fragment.input();
+            (color, Color::WHITE - color)
+        }
+    }
+
+    #[test]
+    fn outputs_are_written_to_their_attachments() {
+        let (mut first, mut second) = (TestBuffer::new(4, 4), TestBuffer::new(4, 4));
+        let red = Color::from([1.0, 0.0, 0.0]);
+        draw(
+            &mut (&mut first, &mut second),
+            None,
+            &MultipleOutputShaders,
+            &quad(0.5, red),
+            &QUAD[..1],
+        );
+
+        let covered = first.covered();
+        assert!(!covered.is_empty());
+        assert_eq!(covered, second.covered());
+        let pixel = PixelCoord { x: 3, y: 3 };
+        assert_eq!(first.get(pixel), red);
+        assert_eq!(second.get(pixel), Color::from([0.0, 1.0, 1.0, 0.0]));
+    }
+
+    /// Replaces the depth of every fragment with a constant.
+    struct ConstantDepthShaders(f32);
+
+    impl ShaderModule for ConstantDepthShaders {
+        type VertexInput = (Vector4, Color);
+        type FragmentInput = ();
+        type Output = WithDepth<Color>;
+
+        fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+            (vertex.0, ())
+        }
+
+        fn fragment_shader(&self, _: &Fragment<Self::FragmentInput>) -> WithDepth<Color> {
+            WithDepth {
+                output: Color::WHITE,
+                depth: self.0,
+            }
+        }
+    }
+
+    #[test]
+    fn fragment_shader_replaces_depth() {
+        let mut pixels = TestBuffer::new(4, 4);
+        let mut depth = DepthBuffer::new(pixels.size);
+        depth.clear(0.75);
+
+        // the quad passes the depth test, but the depth written by the shader decides
+        let vertices = quad(0.5, Color::WHITE);
+        let rasterizer = Rasterizer::default();
+
+        let shaders = ConstantDepthShaders(0.9);
+        rasterizer.draw(&mut pixels, Some(&mut depth), &shaders, &vertices, &QUAD);
+        assert!(pixels.pixels.iter().all(|&color| color == Color::BLACK));
+        assert_eq!(depth.get(PixelCoord { x: 1, y: 2 }), 0.75);
+
+        let shaders = ConstantDepthShaders(-1.0);
+        rasterizer.draw(&mut pixels, Some(&mut depth), &shaders, &vertices, &QUAD);
+        assert!(pixels.pixels.iter().all(|&color| color == Color::WHITE));
+        assert_eq!(depth.get(PixelCoord { x: 1, y: 2 }), 0.0);
+
+        depth.clear(1.0);
+        rasterizer.draw_depth(&mut depth, &ConstantDepthShaders(0.25), &vertices, &QUAD);
+        assert_eq!(depth.get(PixelCoord { x: 3, y: 3 }), 0.25);
+    }
+}
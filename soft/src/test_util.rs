@@ -0,0 +1,101 @@
+//! Framebuffers, geometry and shaders shared by the tests of the rasterizer.
+
+use crate::*;
+
+/// A framebuffer of colors, cleared to black.
+pub struct TestBuffer {
+    pub size: Dimensions,
+    pub pixels: Vec<Color>,
+}
+
+impl TestBuffer {
+    pub fn new(width: u32, height: u32) -> TestBuffer {
+        TestBuffer {
+            size: Dimensions::from([width, height]),
+            pixels: vec![Color::BLACK; (width * height) as usize],
+        }
+    }
+
+    /// The pixels which are no longer black, in row-major order.
+    pub fn covered(&self) -> Vec<(u32, u32)> {
+        let size = self.size;
+        (0..size.height)
+            .flat_map(|y| (0..size.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.get(PixelCoord { x, y }) != Color::BLACK)
+            .collect()
+    }
+}
+
+impl PixelBuffer for TestBuffer {
+    fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    fn get(&self, pixel: PixelCoord) -> Color {
+        self.pixels[(pixel.x + pixel.y * self.size.width) as usize]
+    }
+
+    fn set(&mut self, pixel: PixelCoord, color: Color) {
+        self.pixels[(pixel.x + pixel.y * self.size.width) as usize] = color;
+    }
+}
+
+/// The triangles of a [`quad`].
+pub const QUAD: [Triangle<VertexIndex>; 2] = [
+    Triangle {
+        vertices: [0, 1, 2],
+    },
+    Triangle {
+        vertices: [2, 3, 0],
+    },
+];
+
+/// A quad covering the whole framebuffer at the given depth.
+pub fn quad(z: f32, color: Color) -> Vec<(Vector4, Color)> {
+    let corner = |x, y| (Vector4::new(x, y, z, 1.0), color);
+    vec![
+        corner(-1.0, -1.0),
+        corner(1.0, -1.0),
+        corner(1.0, 1.0),
+        corner(-1.0, 1.0),
+    ]
+}
+
+/// The position of a point given in pixels on an 8x8 framebuffer.
+pub fn screen_position(x: f32, y: f32) -> Vector4 {
+    Vector4::new(x / 4.0 - 1.0, 1.0 - y / 4.0, 0.5, 1.0)
+}
+
+/// Passes the color of each vertex through to the fragments.
+pub struct FlatShaders;
+
+impl ShaderModule for FlatShaders {
+    type VertexInput = (Vector4, Color);
+    type FragmentInput = Color;
+    type Output = Color;
+
+    fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+        *vertex
+    }
+
+    fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> Color {
+        *fragment.input()
+    }
+}
+
+/// Like [`FlatShaders`], but discards fragments with an alpha below one half.
+pub struct DiscardShaders;
+
+impl ShaderModule for DiscardShaders {
+    type VertexInput = (Vector4, Color);
+    type FragmentInput = Color;
+    type Output = Option<Color>;
+
+    fn vertex_shader(&self, vertex: &Self::VertexInput) -> (Vector4, Self::FragmentInput) {
+        *vertex
+    }
+
+    fn fragment_shader(&self, fragment: &Fragment<Self::FragmentInput>) -> Option<Color> {
+        Some(*fragment.input()).filter(|color| color.a >= 0.5)
+    }
+}
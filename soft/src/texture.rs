@@ -0,0 +1,459 @@
+//! Textures and the different ways of sampling them.
+
+use std::ops::{Add, Mul};
+
+use crate::{Color, CompareFunction, DepthBuffer, Dimensions, PixelBuffer, PixelCoord, Vector2};
+
+#[derive(Clone)]
+pub struct Texture<T> {
+    size: Dimensions,
+    pixels: Vec<T>,
+    /// Successively halved versions of the texture, starting with half the size of the texture.
+    mipmaps: Vec<Texture<T>>,
+}
+
+/// How texture coordinates outside of the range `[0, 1]` are mapped onto the texture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddressMode {
+    /// Coordinates are clamped to the edges of the texture.
+    Clamp,
+    /// The texture is tiled.
+    Repeat,
+    /// The texture is tiled, with every other tile mirrored.
+    MirroredRepeat,
+}
+
+impl<T> Texture<T> {
+    pub fn new(pixels: Vec<T>, size: Dimensions) -> Texture<T> {
+        assert_eq!(pixels.len(), size.width as usize * size.height as usize);
+
+        Texture {
+            size,
+            pixels,
+            mipmaps: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    /// The texels of the full size texture, one row after another.
+    pub fn pixels(&self) -> &[T] {
+        &self.pixels
+    }
+
+    /// Number of mipmap levels, including the full size texture.
+    pub fn mip_levels(&self) -> usize {
+        1 + self.mipmaps.len()
+    }
+
+    /// Get a mipmap level of the texture, where level `0` is the full size texture.
+    pub fn level(&self, level: usize) -> &Texture<T> {
+        match level {
+            0 => self,
+            _ => &self.mipmaps[level - 1],
+        }
+    }
+
+    /// The level of detail at which one pixel on the screen covers about one texel, given the
+    /// screen-space derivatives of the texture coordinates.
+    pub fn lod(&self, ddx: Vector2, ddy: Vector2) -> f32 {
+        let width = self.size.width as f32;
+        let height = self.size.height as f32;
+        let texels = |d: Vector2| Vector2::new(d.x * width, d.y * height).length2();
+        0.5 * texels(ddx).max(texels(ddy)).log2()
+    }
+
+    #[inline(always)]
+    fn texel(&self, x: u32, y: u32) -> &T {
+        &self.pixels[x as usize + y as usize * self.size.width as usize]
+    }
+
+    /// Linearly interpolate between the values of the four texels closest to a point.
+    #[inline(always)]
+    fn filter_bilinear<U>(&self, coord: Vector2, address: AddressMode, value: impl Fn(&T) -> U) -> U
+    where
+        U: Copy + Add<U, Output = U> + Mul<f32, Output = U>,
+    {
+        let u = coord.x * self.size.width as f32 - 0.5;
+        let v = coord.y * self.size.height as f32 - 0.5;
+        let (left, top) = (u.floor(), v.floor());
+        let (fx, fy) = (u - left, v - top);
+        let (left, top) = (left as i64, top as i64);
+
+        let fetch = |x: i64, y: i64| {
+            let x = address.wrap(x, self.size.width);
+            let y = address.wrap(y, self.size.height);
+            value(self.texel(x, y))
+        };
+
+        let upper = fetch(left, top) * (1.0 - fx) + fetch(left + 1, top) * fx;
+        let lower = fetch(left, top + 1) * (1.0 - fx) + fetch(left + 1, top + 1) * fx;
+        upper * (1.0 - fy) + lower * fy
+    }
+}
+
+impl<T: Clone> Texture<T> {
+    /// Create a texture where every texel has the same value, such as a render target cleared to
+    /// a color.
+    pub fn filled(size: Dimensions, value: T) -> Texture<T> {
+        Texture::new(
+            vec![value; size.width as usize * size.height as usize],
+            size,
+        )
+    }
+
+    /// Set every texel to the same value, removing any mipmaps.
+    pub fn clear(&mut self, value: T) {
+        self.pixels
+            .iter_mut()
+            .for_each(|texel| *texel = value.clone());
+        self.mipmaps.clear();
+    }
+}
+
+impl<T: Clone + Default> Texture<T> {
+    pub fn sample_nearest(&self, coord: Vector2) -> T {
+        if 0.0 <= coord.x && coord.x < 1.0 && 0.0 <= coord.y && coord.y < 1.0 {
+            let x = coord.x * (self.size.width) as f32;
+            let y = coord.y * (self.size.height) as f32;
+            let index = x as usize + y as usize * self.size.width as usize;
+            self.pixels[index].clone()
+        } else {
+            T::default()
+        }
+    }
+
+    #[allow(clippy::manual_clamp)]
+    pub fn sample_nearest_clamp(&self, coord: Vector2) -> T {
+        let clamped = Vector2 {
+            x: coord.x.max(0.0).min(1.0 - f32::EPSILON),
+            y: coord.y.max(0.0).min(1.0 - f32::EPSILON),
+        };
+        self.sample_nearest(clamped)
+    }
+
+    pub fn sample_nearest_repeat(&self, coord: Vector2) -> T {
+        let repeated = Vector2 {
+            x: coord.x - coord.x.floor(),
+            y: coord.y - coord.y.floor(),
+        };
+        self.sample_nearest(repeated)
+    }
+
+    pub fn sample_nearest_mirrored_repeat(&self, coord: Vector2) -> T {
+        let mirror = |coord: f32| {
+            let coord = coord.rem_euclid(2.0);
+            let mirrored = if coord > 1.0 { 2.0 - coord } else { coord };
+            mirrored.min(1.0 - f32::EPSILON)
+        };
+        let mirrored = Vector2 {
+            x: mirror(coord.x),
+            y: mirror(coord.y),
+        };
+        self.sample_nearest(mirrored)
+    }
+}
+
+impl<T> Texture<T>
+where
+    T: Copy + Add<T, Output = T> + Mul<f32, Output = T>,
+{
+    /// Sample the texture, linearly interpolating between the four closest texels.
+    pub fn sample_bilinear(&self, coord: Vector2, address: AddressMode) -> T {
+        self.filter_bilinear(coord, address, |texel| *texel)
+    }
+
+    /// Sample the texture at the given level of detail (LOD), where `0` is the full size texture
+    /// and every increment halves the resolution. Bilinear samples from the two closest mipmap
+    /// levels are linearly interpolated. The LOD is clamped to the available mipmap levels, and a
+    /// LOD that is not a number samples the full size texture.
+    pub fn sample_trilinear(&self, coord: Vector2, lod: f32, address: AddressMode) -> T {
+        let lod = lod.clamp(0.0, self.mipmaps.len() as f32);
+        let level = lod.floor() as usize;
+        let fraction = lod - level as f32;
+
+        let near = self.level(level).sample_bilinear(coord, address);
+        if fraction == 0.0 || !lod.is_finite() || level == self.mipmaps.len() {
+            return near;
+        }
+
+        let far = self.level(level + 1).sample_bilinear(coord, address);
+        near * (1.0 - fraction) + far * fraction
+    }
+
+    /// Sample the texture using trilinear filtering, selecting the level of detail from the
+    /// screen-space derivatives of the texture coordinates, such as those given by
+    /// [`Fragment::dfdx`](crate::Fragment::dfdx) and [`Fragment::dfdy`](crate::Fragment::dfdy).
+    pub fn sample_grad(
+        &self,
+        coord: Vector2,
+        ddx: Vector2,
+        ddy: Vector2,
+        address: AddressMode,
+    ) -> T {
+        self.sample_trilinear(coord, self.lod(ddx, ddy), address)
+    }
+
+    /// Generate a chain of mipmaps, where every level is half the size of the previous, down to a
+    /// single texel. Replaces any previously generated mipmaps.
+    pub fn generate_mipmaps(&mut self) {
+        let mut mipmaps: Vec<Texture<T>> = Vec::new();
+        let mut size = self.size;
+        while size.width > 1 || size.height > 1 {
+            let next = mipmaps.last().unwrap_or(&*self).downsample();
+            size = next.size;
+            mipmaps.push(next);
+        }
+        self.mipmaps = mipmaps;
+    }
+
+    /// Halve the size of the texture by averaging blocks of 2x2 texels.
+    fn downsample(&self) -> Texture<T> {
+        let size = Dimensions {
+            width: (self.size.width / 2).max(1),
+            height: (self.size.height / 2).max(1),
+        };
+
+        let max_x = self.size.width - 1;
+        let max_y = self.size.height - 1;
+        let mut pixels = Vec::with_capacity(size.width as usize * size.height as usize);
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let (left, right) = (2 * x, (2 * x + 1).min(max_x));
+                let (top, bottom) = (2 * y, (2 * y + 1).min(max_y));
+                let sum = *self.texel(left, top)
+                    + *self.texel(right, top)
+                    + *self.texel(left, bottom)
+                    + *self.texel(right, bottom);
+                pixels.push(sum * 0.25);
+            }
+        }
+
+        Texture::new(pixels, size)
+    }
+}
+
+/// Textures can be drawn to, and then sampled in a later pass. Drawing removes any mipmaps, since
+/// they would no longer match the texture, so they have to be generated again afterwards.
+impl PixelBuffer for Texture<Color> {
+    fn size(&self) -> Dimensions {
+        self.size
+    }
+
+    fn get(&self, pixel: PixelCoord) -> Color {
+        *self.texel(pixel.x, pixel.y)
+    }
+
+    fn set(&mut self, pixel: PixelCoord, color: Color) {
+        let index = pixel.x as usize + pixel.y as usize * self.size.width as usize;
+        self.pixels[index] = color;
+        self.mipmaps.clear();
+    }
+}
+
+/// Sample the depths rendered to a depth buffer, for example as a shadow map. The stencil values
+/// are dropped.
+impl From<DepthBuffer> for Texture<f32> {
+    fn from(depth_buffer: DepthBuffer) -> Self {
+        Texture::new(depth_buffer.depths, depth_buffer.size)
+    }
+}
+
+/// Draw to a depth texture, without a stencil attachment. Mipmaps are dropped.
+impl From<Texture<f32>> for DepthBuffer {
+    fn from(texture: Texture<f32>) -> Self {
+        DepthBuffer {
+            size: texture.size,
+            depths: texture.pixels,
+            stencil: None,
+        }
+    }
+}
+
+impl Texture<f32> {
+    /// Compare a depth against the depths stored in the texture, such as a shadow map, returning
+    /// the fraction of the comparisons that pass. The given depth is on the left-hand side of the
+    /// comparison, so with [`CompareFunction::LessEqual`] this is the fraction of the texels the
+    /// depth is in front of. Like comparison samplers on a GPU the results of the four closest
+    /// texels are bilinearly interpolated, to smooth the edges of shadows. Coordinates outside the
+    /// texture are clamped to its edges.
+    pub fn sample_compare(&self, coord: Vector2, depth: f32, compare: CompareFunction) -> f32 {
+        self.filter_bilinear(coord, AddressMode::Clamp, |&stored| {
+            if compare.test(depth, stored) {
+                1.0
+            } else {
+                0.0
+            }
+        })
+    }
+
+    /// Percentage-closer filtering: average [`sample_compare`](Texture::sample_compare) over a
+    /// square grid of `kernel_size` by `kernel_size` points one texel apart, centered on the
+    /// coordinate. Larger kernels give softer shadow edges.
+    pub fn sample_compare_pcf(
+        &self,
+        coord: Vector2,
+        depth: f32,
+        compare: CompareFunction,
+        kernel_size: u32,
+    ) -> f32 {
+        if kernel_size <= 1 {
+            return self.sample_compare(coord, depth, compare);
+        }
+
+        let texel = Vector2::new(1.0 / self.size.width as f32, 1.0 / self.size.height as f32);
+        let center = (kernel_size - 1) as f32 / 2.0;
+        let mut sum = 0.0;
+        for y in 0..kernel_size {
+            for x in 0..kernel_size {
+                let offset =
+                    Vector2::new((x as f32 - center) * texel.x, (y as f32 - center) * texel.y);
+                sum += self.sample_compare(coord + offset, depth, compare);
+            }
+        }
+        sum / (kernel_size * kernel_size) as f32
+    }
+}
+
+impl AddressMode {
+    /// Map a texel index onto a texture with the given number of texels.
+    #[inline(always)]
+    fn wrap(self, index: i64, size: u32) -> u32 {
+        let size = size as i64;
+        let wrapped = match self {
+            AddressMode::Clamp => index.clamp(0, size - 1),
+            AddressMode::Repeat => index.rem_euclid(size),
+            AddressMode::MirroredRepeat => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+        wrapped as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw;
+    use crate::test_util::*;
+
+    fn gradient() -> Texture<f32> {
+        Texture::new(vec![0.0, 1.0, 2.0, 3.0], [4, 1].into())
+    }
+
+    #[test]
+    fn bilinear_interpolates_between_texels() {
+        let texture = gradient();
+        let sample = |x: f32, address| texture.sample_bilinear(Vector2::new(x, 0.5), address);
+
+        assert_eq!(sample(0.25, AddressMode::Clamp), 0.5);
+        assert_eq!(sample(0.0, AddressMode::Clamp), 0.0);
+        assert_eq!(sample(0.0, AddressMode::Repeat), 1.5);
+        assert_eq!(sample(0.0, AddressMode::MirroredRepeat), 0.0);
+        assert_eq!(sample(1.0, AddressMode::Repeat), 1.5);
+    }
+
+    #[test]
+    fn address_modes() {
+        let wrap = |address: AddressMode| (-3..7).map(|i| address.wrap(i, 3)).collect::<Vec<_>>();
+        assert_eq!(wrap(AddressMode::Clamp), [0, 0, 0, 0, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(wrap(AddressMode::Repeat), [0, 1, 2, 0, 1, 2, 0, 1, 2, 0]);
+        assert_eq!(
+            wrap(AddressMode::MirroredRepeat),
+            [2, 1, 0, 0, 1, 2, 2, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn mipmaps_average_texels() {
+        let mut texture = gradient();
+        texture.generate_mipmaps();
+
+        assert_eq!(texture.mip_levels(), 3);
+        assert_eq!(texture.level(1).pixels, [0.5, 2.5]);
+        assert_eq!(texture.level(2).pixels, [1.5]);
+
+        let center = Vector2::new(0.5, 0.5);
+        assert_eq!(
+            texture.sample_trilinear(center, 2.0, AddressMode::Clamp),
+            1.5
+        );
+        assert_eq!(
+            texture.sample_trilinear(center, 10.0, AddressMode::Clamp),
+            1.5
+        );
+        assert_eq!(
+            texture.sample_trilinear(Vector2::new(0.125, 0.5), 0.5, AddressMode::Clamp),
+            0.5 * 0.0 + 0.5 * 0.5
+        );
+    }
+
+    #[test]
+    fn trilinear_without_mipmaps() {
+        let texture = gradient();
+        let sample =
+            |lod| texture.sample_trilinear(Vector2::new(0.125, 0.5), lod, AddressMode::Clamp);
+
+        assert_eq!(sample(0.5), 0.0);
+        assert_eq!(sample(f32::NAN), 0.0);
+        assert_eq!(sample(f32::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn comparison_sampling_and_pcf() {
+        let shadow_map = gradient();
+        let sample = |x: f32, kernel_size: u32| {
+            let coord = Vector2::new(x, 0.5);
+            shadow_map.sample_compare_pcf(coord, 1.5, CompareFunction::LessEqual, kernel_size)
+        };
+
+        // texels 2 and 3 are behind the depth, the result is interpolated between texels
+        assert_eq!(sample(0.625, 1), 1.0);
+        assert_eq!(sample(0.375, 1), 0.0);
+        assert_eq!(sample(0.5, 1), 0.5);
+        // a 3x3 kernel averages the texel and its neighbours
+        assert_eq!(sample(0.625, 3), 2.0 / 3.0);
+        assert_eq!(sample(0.875, 2), 1.0);
+    }
+
+    #[test]
+    fn lod_from_derivatives() {
+        let texture = gradient();
+        let lod = |dx: f32, dy: f32| texture.lod(Vector2::new(dx, 0.0), Vector2::new(0.0, dy));
+
+        assert_eq!(lod(0.25, 1.0), 0.0);
+        assert_eq!(lod(0.5, 0.0), 1.0);
+        assert_eq!(lod(0.125, 4.0), 2.0);
+    }
+
+    #[test]
+    fn render_to_texture() {
+        let size = Dimensions::from([4, 4]);
+        let mut color = Texture::filled(size, Color::BLACK);
+        let mut depth = DepthBuffer::from(Texture::filled(size, 1.0));
+        let red = Color::from([1.0, 0.0, 0.0]);
+        let vertices = quad(0.5, red);
+        draw(
+            &mut color,
+            Some(&mut depth),
+            &FlatShaders,
+            &vertices,
+            &QUAD[..1],
+        );
+
+        let depth = Texture::from(depth);
+        let bottom_right = Vector2::new(0.875, 0.875);
+        let top_left = Vector2::new(0.125, 0.125);
+        assert_eq!(color.sample_nearest(bottom_right), red);
+        assert_eq!(color.sample_nearest(top_left), Color::BLACK);
+        assert_eq!(depth.sample_nearest(bottom_right), 0.5);
+        assert_eq!(depth.sample_nearest(top_left), 1.0);
+    }
+}
@@ -0,0 +1,114 @@
+//! Assembly of triangles from sequences of vertex indices.
+
+use crate::{Triangle, VertexIndex};
+
+/// How a sequence of vertices is assembled into triangles.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Topology {
+    /// Every three vertices form a separate triangle.
+    TriangleList,
+    /// Every vertex forms a triangle with the two vertices before it. Every other triangle is
+    /// flipped so that all triangles in the strip have the same winding order.
+    TriangleStrip,
+    /// Every vertex forms a triangle with the vertex before it and the first vertex.
+    TriangleFan,
+}
+
+/// An index that ends the current strip or fan, so that the following vertices start a new one.
+/// In a list it discards the vertices of the incomplete triangle before it.
+pub const PRIMITIVE_RESTART: VertexIndex = VertexIndex::MAX;
+
+impl Topology {
+    /// Assemble a sequence of vertex indices into a list of triangles.
+    pub fn triangles(self, indices: &[VertexIndex]) -> Vec<Triangle<VertexIndex>> {
+        let mut triangles = Vec::with_capacity(indices.len());
+        for run in indices.split(|&index| index == PRIMITIVE_RESTART) {
+            match self {
+                Topology::TriangleList => triangles.extend(
+                    run.chunks_exact(3)
+                        .map(|triangle| Triangle::from([triangle[0], triangle[1], triangle[2]])),
+                ),
+                Topology::TriangleStrip => {
+                    triangles.extend(run.windows(3).enumerate().map(|(i, strip)| {
+                        if i & 1 == 0 {
+                            Triangle::from([strip[0], strip[1], strip[2]])
+                        } else {
+                            Triangle::from([strip[1], strip[0], strip[2]])
+                        }
+                    }))
+                }
+                Topology::TriangleFan => {
+                    if let Some((&first, rest)) = run.split_first() {
+                        triangles.extend(
+                            rest.windows(2)
+                                .map(|edge| Triangle::from([first, edge[0], edge[1]])),
+                        );
+                    }
+                }
+            }
+        }
+        triangles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::{Color, Rasterizer};
+
+    fn triangles(topology: Topology, indices: &[VertexIndex]) -> Vec<[VertexIndex; 3]> {
+        topology
+            .triangles(indices)
+            .into_iter()
+            .map(|triangle| triangle.vertices)
+            .collect()
+    }
+
+    #[test]
+    fn strips_keep_winding_order() {
+        let strip = triangles(Topology::TriangleStrip, &[0, 1, 2, 3, 4]);
+        assert_eq!(strip, [[0, 1, 2], [2, 1, 3], [2, 3, 4]]);
+
+        let fan = triangles(Topology::TriangleFan, &[0, 1, 2, 3]);
+        assert_eq!(fan, [[0, 1, 2], [0, 2, 3]]);
+
+        let list = triangles(Topology::TriangleList, &[0, 1, 2, 3, 4]);
+        assert_eq!(list, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn primitive_restart() {
+        let indices = [0, 1, 2, 3, PRIMITIVE_RESTART, 4, 5, 6, PRIMITIVE_RESTART, 7];
+        let strip = triangles(Topology::TriangleStrip, &indices);
+        assert_eq!(strip, [[0, 1, 2], [2, 1, 3], [4, 5, 6]]);
+
+        let fan = triangles(Topology::TriangleFan, &indices);
+        assert_eq!(fan, [[0, 1, 2], [0, 2, 3], [4, 5, 6]]);
+
+        let list = triangles(Topology::TriangleList, &[0, 1, PRIMITIVE_RESTART, 2, 3, 4]);
+        assert_eq!(list, [[2, 3, 4]]);
+    }
+
+    #[test]
+    fn draw_arrays_assembles_strips() {
+        let mut pixels = TestBuffer::new(4, 4);
+
+        // zig-zag through the corners of the framebuffer
+        let mut vertices = quad(0.5, Color::WHITE);
+        vertices.swap(1, 3);
+        vertices.swap(2, 3);
+        let statistics = Rasterizer::default().draw_arrays(
+            &mut pixels,
+            None,
+            &FlatShaders,
+            &vertices,
+            Topology::TriangleStrip,
+            0..4,
+        );
+
+        assert!(pixels.pixels.iter().all(|&color| color == Color::WHITE));
+        assert_eq!(statistics.vertex_shader_invocations, 6);
+        assert_eq!(statistics.vertex_cache_hits, 0);
+    }
+}
@@ -11,15 +11,7 @@ pub fn derive_interpolate(input: TokenStream) -> TokenStream {
         Err(e) => e.to_compile_error().into(),
         Ok(item) => {
             let field_init = item.fields.into_iter().map(|Field { name, ty }| {
-                let name = match name {
-                    FieldName::Ident(ident) => quote!(#ident),
-                    FieldName::Index(index, span) => {
-                        let index = proc_macro::Literal::u32_unsuffixed(index);
-                        let stream = TokenStream::from(TokenTree::Literal(index));
-                        let stream2 = proc_macro2::TokenStream::from(stream);
-                        quote_spanned!(span=> #stream2)
-                    },
-                };
+                let name = name.tokens();
                 quote! {
                     #name: <#ty as soft::Interpolate>::tri_lerp(
                         &[values[0].#name, values[1].#name, values[2].#name],
@@ -43,6 +35,63 @@ pub fn derive_interpolate(input: TokenStream) -> TokenStream {
     }
 }
 
+#[proc_macro_derive(FragmentOutput)]
+pub fn derive_fragment_output(input: TokenStream) -> TokenStream {
+    match parse_item(input) {
+        Err(e) => e.to_compile_error().into(),
+        Ok(item) => {
+            let types = item
+                .fields
+                .iter()
+                .map(|field| &field.ty)
+                .collect::<Vec<_>>();
+            let field_color = item.fields.iter().map(|Field { name, ty }| {
+                let name = name.tokens();
+                quote! {
+                    if index < <#ty as soft::FragmentOutput>::COUNT {
+                        return soft::FragmentOutput::color(&self.#name, index);
+                    }
+                    index -= <#ty as soft::FragmentOutput>::COUNT;
+                }
+            });
+            let field_discarded = item.fields.iter().map(|Field { name, .. }| {
+                let name = name.tokens();
+                quote!(soft::FragmentOutput::discarded(&self.#name))
+            });
+            let field_depth = item.fields.iter().map(|Field { name, .. }| {
+                let name = name.tokens();
+                quote! {
+                    .or_else(|| soft::FragmentOutput::depth(&self.#name))
+                }
+            });
+
+            let name = item.name;
+            let output = quote! {
+                impl soft::FragmentOutput for #name {
+                    const COUNT: usize = 0 #(+ <#types as soft::FragmentOutput>::COUNT)*;
+                    const DISCARDS: bool = false #(|| <#types as soft::FragmentOutput>::DISCARDS)*;
+                    const WRITES_DEPTH: bool =
+                        false #(|| <#types as soft::FragmentOutput>::WRITES_DEPTH)*;
+
+                    fn color(&self, mut index: usize) -> soft::Color {
+                        #(#field_color)*
+                        panic!("fragment output index out of range")
+                    }
+
+                    fn discarded(&self) -> bool {
+                        false #(|| #field_discarded)*
+                    }
+
+                    fn depth(&self) -> Option<f32> {
+                        None #(#field_depth)*
+                    }
+                }
+            };
+            output.into()
+        }
+    }
+}
+
 struct Item {
     name: syn::Ident,
     fields: Vec<Field>,
@@ -58,6 +107,20 @@ enum FieldName {
     Index(u32, Span),
 }
 
+impl FieldName {
+    fn tokens(&self) -> proc_macro2::TokenStream {
+        match *self {
+            FieldName::Ident(ref ident) => quote!(#ident),
+            FieldName::Index(index, span) => {
+                let index = proc_macro::Literal::u32_unsuffixed(index);
+                let stream = TokenStream::from(TokenTree::Literal(index));
+                let stream2 = proc_macro2::TokenStream::from(stream);
+                quote_spanned!(span=> #stream2)
+            }
+        }
+    }
+}
+
 fn parse_item(input: TokenStream) -> syn::Result<Item> {
     let item = syn::parse::<syn::DeriveInput>(input)?;
 